@@ -0,0 +1,18 @@
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+/// Runs `f` `iterations` times and prints the mean time taken per run.
+pub fn bench<T, F>(label: &str, iterations: u32, mut f: F) -> Duration
+where
+    F: FnMut() -> T,
+{
+    let start = Instant::now();
+    for _ in 0..iterations {
+        black_box(f());
+    }
+    let mean = start.elapsed() / iterations;
+    println!("{:<32} {:>12.3?}", label, mean);
+    mean
+}
@@ -1,52 +1,139 @@
-use std::{fs::File, io};
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::File,
+    io::{self, BufRead},
+};
 
 use rstest::rstest;
 
 use crate::fs;
 
-struct CaloriesInput {
-    lines: io::Lines<io::BufReader<File>>,
-    processing: bool,
-    current: u32,
+#[derive(Clone, Debug, PartialEq)]
+struct Elf {
+    /// Numbered from 1 in the order the elves appear in the input
+    index: usize,
+    items: Vec<u32>,
 }
 
-impl Iterator for CaloriesInput {
-    type Item = u32;
+impl Elf {
+    fn calories(&self) -> u32 {
+        self.items.iter().sum()
+    }
+
+    /// The same total as `calories`, but wide enough for a rebalanced elf, who can end up carrying
+    /// more than any one elf could in the input.
+    fn load(&self) -> u64 {
+        self.items.iter().map(|&item| item as u64).sum()
+    }
+}
+
+struct CaloriesInput<B> {
+    lines: io::Lines<B>,
+    line: usize,
+    elves: usize,
+}
+
+impl<B> Iterator for CaloriesInput<B>
+where
+    B: BufRead,
+{
+    type Item = Result<Elf, String>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        for line in &mut self.lines {
-            match line {
-                Ok(line) => match &line[..] {
-                    "" => break,
-                    _ => {
-                        let calories = line.parse::<u32>().expect("calories not parseable as u32");
-                        self.current += calories;
-                        self.processing = true;
-                    }
-                },
-                Err(_) => {}
+        let mut items = Vec::new();
+        let mut total = 0_u32;
+        for line in self.lines.by_ref().map_while(Result::ok) {
+            self.line += 1;
+            if line.is_empty() {
+                break;
             }
+            let calories = match line.parse::<u32>() {
+                Ok(calories) => calories,
+                Err(e) => return Some(Err(format!("line {}: '{}': {}", self.line, line, e))),
+            };
+            total = match total.checked_add(calories) {
+                Some(total) => total,
+                None => {
+                    return Some(Err(format!(
+                        "line {}: elf {} is carrying more than {} calories",
+                        self.line,
+                        self.elves + 1,
+                        u32::MAX
+                    )))
+                }
+            };
+            items.push(calories);
         }
-        if self.processing {
-            let current = self.current;
-            self.current = 0;
-            self.processing = false;
-            Some(current)
-        } else {
-            None
+        if items.is_empty() {
+            return None;
         }
+        self.elves += 1;
+        Some(Ok(Elf {
+            index: self.elves,
+            items,
+        }))
+    }
+}
+
+#[cfg(test)]
+fn elves_from_str(s: &str) -> CaloriesInput<io::Cursor<&[u8]>> {
+    CaloriesInput {
+        lines: io::Cursor::new(s.as_bytes()).lines(),
+        line: 0,
+        elves: 0,
     }
 }
 
-fn file_calories(filename: &str) -> io::Result<CaloriesInput> {
+fn file_elves(filename: &str) -> io::Result<CaloriesInput<io::BufReader<File>>> {
     let lines = fs::read_lines(filename)?;
     Ok(CaloriesInput {
         lines,
-        processing: false,
-        current: 0,
+        line: 0,
+        elves: 0,
     })
 }
 
+#[test]
+fn test_file_elves() {
+    let elves: Result<Vec<Elf>, String> = file_elves("./test01.txt").unwrap().collect();
+    assert_eq!(
+        elves.unwrap()[2..4],
+        [
+            Elf {
+                index: 3,
+                items: vec![5_000, 6_000]
+            },
+            Elf {
+                index: 4,
+                items: vec![7_000, 8_000, 9_000]
+            }
+        ]
+    );
+}
+
+#[rstest]
+#[case("1\n2\n\nx\n", "line 4: 'x': invalid digit found in string")]
+#[case(
+    "4294967296\n",
+    "line 1: '4294967296': number too large to fit in target type"
+)]
+#[case(
+    "1\n\n4294967295\n1\n",
+    "line 4: elf 2 is carrying more than 4294967295 calories"
+)]
+fn test_elves_errors(#[case] s: &str, #[case] error: &str) {
+    assert_eq!(
+        elves_from_str(s).collect::<Result<Vec<Elf>, String>>(),
+        Err(error.to_string())
+    );
+}
+
+/// Each elf's total calories, panicking on input the parser rejects.
+fn file_calories(filename: &str) -> io::Result<impl Iterator<Item = u32>> {
+    Ok(file_elves(filename)?.map(|elf| elf.unwrap_or_else(|e| panic!("{}", e)).calories()))
+}
+
 #[test]
 fn test_file_calories() {
     let elves = file_calories("./test01.txt").unwrap();
@@ -56,42 +143,266 @@ fn test_file_calories() {
     );
 }
 
-pub fn part_1(filename: &str) -> u32 {
-    let mut most_calories = 0_u32;
-    if let Ok(elves) = file_calories(filename) {
-        for calories in elves {
-            if calories > most_calories {
-                most_calories = calories;
-            }
+/// The elf holding the single most calorific item, and that item. Ties go to the earlier elf.
+fn largest_item(elves: &[Elf]) -> Option<(usize, u32)> {
+    elves
+        .iter()
+        .flat_map(|elf| elf.items.iter().map(|&item| (elf.index, item)))
+        .min_by_key(|&(index, item)| (Reverse(item), index))
+}
+
+#[test]
+fn test_largest_item() {
+    let elves: Vec<Elf> = elves_from_str("3\n9\n\n9\n\n1")
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(largest_item(&elves), Some((1, 9)));
+    assert_eq!(largest_item(&[]), None);
+}
+
+/// Hands every item out again so the loads are as even as possible, giving the biggest items out
+/// first, each to whoever is carrying least at the time. That isn't always the best possible
+/// split, but the heaviest load is never more than a third over it.
+fn rebalance(elves: &[Elf]) -> Vec<Elf> {
+    let mut items: Vec<u32> = elves.iter().flat_map(|elf| elf.items.clone()).collect();
+    items.sort_unstable_by_key(|&item| Reverse(item));
+    let mut balanced: Vec<Elf> = elves
+        .iter()
+        .map(|elf| Elf {
+            index: elf.index,
+            items: Vec::new(),
+        })
+        .collect();
+    let mut lightest: BinaryHeap<_> = (0..balanced.len()).map(|i| Reverse((0_u64, i))).collect();
+    for item in items {
+        let Some(Reverse((load, i))) = lightest.pop() else {
+            break;
+        };
+        balanced[i].items.push(item);
+        lightest.push(Reverse((load + item as u64, i)));
+    }
+    balanced
+}
+
+#[rstest]
+#[case::test("1000\n2000\n3000\n\n4000\n\n5000\n6000\n\n7000\n8000\n9000\n\n10000", vec![11_000; 5])]
+// Giving out the biggest items first misses the best split, 8 + 7 + 1 against 6 + 5 + 4
+#[case::not_optimal("8\n7\n6\n5\n4\n\n1", vec![17, 14])]
+#[case::odd_total("3\n3\n2\n2\n2\n\n1", vec![7, 6])]
+#[case::overflow(
+    "2147483646\n2147483646\n\n1431655764\n1431655764\n1431655764",
+    vec![5_010_795_174, 3_579_139_410]
+)]
+fn test_rebalance(#[case] s: &str, #[case] loads: Vec<u64>) {
+    let elves: Vec<Elf> = elves_from_str(s).map(Result::unwrap).collect();
+    let balanced = rebalance(&elves);
+    assert_eq!(balanced.iter().map(Elf::load).collect::<Vec<u64>>(), loads);
+    let mut before: Vec<u32> = elves.iter().flat_map(|elf| elf.items.clone()).collect();
+    let mut after: Vec<u32> = balanced.iter().flat_map(|elf| elf.items.clone()).collect();
+    before.sort_unstable();
+    after.sort_unstable();
+    assert_eq!(before, after);
+}
+
+/// The `k` elves carrying the most calories as `(elf, calories)`, most first. Elves are numbered
+/// from 1 in the order they appear, and ties go to the earlier elf.
+fn top_k(elves: impl Iterator<Item = u32>, k: usize) -> Vec<(usize, u32)> {
+    // A min-heap of the best so far, so the weakest of them is always the one to evict
+    let mut top = BinaryHeap::with_capacity(k + 1);
+    for (i, calories) in elves.enumerate() {
+        top.push(Reverse((calories, Reverse(i + 1))));
+        if top.len() > k {
+            top.pop();
         }
     }
-    most_calories
+    top.into_sorted_vec()
+        .into_iter()
+        .map(|Reverse((calories, Reverse(elf)))| (elf, calories))
+        .collect()
+}
+
+#[rstest]
+#[case(0, vec![])]
+#[case(1, vec![(4, 24_000)])]
+#[case(3, vec![(4, 24_000), (3, 11_000), (5, 10_000)])]
+#[case(9, vec![(4, 24_000), (3, 11_000), (5, 10_000), (1, 6_000), (2, 4_000)])]
+fn test_top_k(#[case] k: usize, #[case] result: Vec<(usize, u32)>) {
+    assert_eq!(top_k(file_calories("./test01.txt").unwrap(), k), result);
+}
+
+#[test]
+fn test_top_k_ties() {
+    assert_eq!(
+        top_k([5, 7, 5, 7, 5].into_iter(), 3),
+        vec![(2, 7), (4, 7), (1, 5)]
+    );
+}
+
+#[derive(Debug, PartialEq)]
+struct CalorieStats {
+    elves: usize,
+    mean: f64,
+    median: f64,
+    top: Vec<(usize, u32)>,
+}
+
+/// Summarises every elf's load in a single pass over `elves`, along with the `k` most laden.
+fn calorie_stats(elves: impl Iterator<Item = u32>, k: usize) -> CalorieStats {
+    let mut totals = Vec::new();
+    let mut sum = 0_u64;
+    let top = top_k(
+        elves.inspect(|&calories| {
+            totals.push(calories);
+            sum += calories as u64;
+        }),
+        k,
+    );
+    totals.sort_unstable();
+    let n = totals.len();
+    let median = match n {
+        0 => 0.0,
+        _ if n % 2 == 0 => (totals[n / 2 - 1] as f64 + totals[n / 2] as f64) / 2.0,
+        _ => totals[n / 2] as f64,
+    };
+    CalorieStats {
+        elves: n,
+        mean: if n == 0 { 0.0 } else { sum as f64 / n as f64 },
+        median,
+        top,
+    }
+}
+
+#[test]
+fn test_calorie_stats() {
+    assert_eq!(
+        calorie_stats(file_calories("./test01.txt").unwrap(), 2),
+        CalorieStats {
+            elves: 5,
+            mean: 11_000.0,
+            median: 10_000.0,
+            top: vec![(4, 24_000), (3, 11_000)],
+        }
+    );
+    assert_eq!(calorie_stats([1, 2, 3, 10].into_iter(), 0).median, 2.5);
+}
+
+/// Which `k` elves are carrying the most calories, and how far above the mean each of them is.
+pub fn top(filename: &str, k: usize) -> String {
+    let stats = calorie_stats(file_calories(filename).unwrap(), k);
+    let mut result = format!(
+        "{} elves, mean {:.1}, median {:.1}\n",
+        stats.elves, stats.mean, stats.median
+    );
+    for (rank, &(elf, calories)) in stats.top.iter().enumerate() {
+        result.push_str(&format!(
+            "#{} elf {}: {} ({:+.1} on the mean)\n",
+            rank + 1,
+            elf,
+            calories,
+            calories as f64 - stats.mean
+        ));
+    }
+    result.push_str(&format!(
+        "top {} total: {}\n",
+        stats.top.len(),
+        stats
+            .top
+            .iter()
+            .map(|&(_, calories)| calories as u64)
+            .sum::<u64>()
+    ));
+    result
+}
+
+#[test]
+fn test_top() {
+    assert_eq!(
+        top("./test01.txt", 3),
+        "5 elves, mean 11000.0, median 10000.0\n\
+         #1 elf 4: 24000 (+13000.0 on the mean)\n\
+         #2 elf 3: 11000 (+0.0 on the mean)\n\
+         #3 elf 5: 10000 (-1000.0 on the mean)\n\
+         top 3 total: 45000\n"
+    );
+    assert_eq!(
+        top("./test01-3.txt", 2),
+        "2 elves, mean 3000000000.0, median 3000000000.0\n\
+         #1 elf 1: 3000000000 (+0.0 on the mean)\n\
+         #2 elf 2: 3000000000 (+0.0 on the mean)\n\
+         top 2 total: 6000000000\n"
+    );
+}
+
+fn elves_from_file(filename: &str) -> Result<Vec<Elf>, String> {
+    file_elves(filename).map_err(|e| e.to_string())?.collect()
+}
+
+/// Which elf is holding the single largest item.
+pub fn largest(filename: &str) -> String {
+    match largest_item(&elves_from_file(filename).unwrap()) {
+        Some((elf, item)) => format!("elf {}: {}\n", elf, item),
+        None => "No elves\n".to_string(),
+    }
+}
+
+#[test]
+fn test_largest() {
+    assert_eq!(largest("./test01.txt"), "elf 5: 10000\n");
+}
+
+/// How much the heaviest and lightest loads differ before and after rebalancing, and the new
+/// loads.
+pub fn balance(filename: &str) -> String {
+    let elves = elves_from_file(filename).unwrap();
+    let balanced = rebalance(&elves);
+    let spread = |elves: &[Elf]| {
+        let loads = elves.iter().map(Elf::load);
+        loads.clone().max().unwrap_or(0) - loads.min().unwrap_or(0)
+    };
+    let mut result = format!("spread: {} -> {}\n", spread(&elves), spread(&balanced));
+    for elf in balanced {
+        result.push_str(&format!(
+            "elf {}: {} {:?}\n",
+            elf.index,
+            elf.load(),
+            elf.items
+        ));
+    }
+    result
+}
+
+#[test]
+fn test_balance() {
+    assert_eq!(
+        balance("./test01.txt"),
+        "spread: 20000 -> 0\n\
+         elf 1: 11000 [10000, 1000]\n\
+         elf 2: 11000 [9000, 2000]\n\
+         elf 3: 11000 [8000, 3000]\n\
+         elf 4: 11000 [7000, 4000]\n\
+         elf 5: 11000 [6000, 5000]\n"
+    );
+}
+
+pub fn part_1(filename: &str) -> u32 {
+    top_k(file_calories(filename).unwrap(), 1)
+        .first()
+        .map_or(0, |&(_, calories)| calories)
 }
 
 #[rstest]
 #[case::test("./test01.txt", 24000)]
+#[case::empty("./test01-2.txt", 0)]
 #[case::input("./input01.txt", 69795)]
 fn test_part_1(#[case] filename: &str, #[case] result: u32) {
     assert_eq!(part_1(filename), result);
 }
 
 pub fn part_2(filename: &str) -> u32 {
-    let mut top_three = [0_u32; 3];
-    if let Ok(elves) = file_calories(filename) {
-        for calories in elves {
-            if calories >= top_three[0] {
-                top_three[2] = top_three[1];
-                top_three[1] = top_three[0];
-                top_three[0] = calories;
-            } else if calories >= top_three[1] {
-                top_three[2] = top_three[1];
-                top_three[1] = calories;
-            } else if calories > top_three[2] {
-                top_three[2] = calories;
-            }
-        }
-    }
-    top_three.iter().sum()
+    top_k(file_calories(filename).unwrap(), 3)
+        .iter()
+        .map(|&(_, calories)| calories)
+        .sum()
 }
 
 #[rstest]
@@ -1,49 +1,151 @@
 use std::{fs::File, io};
 
+use itertools::Itertools;
 use rstest::rstest;
 
 use crate::fs;
 
-#[derive(Clone, Copy)]
-enum Move {
-    Rock,
-    Paper,
-    Scissors,
+/// A move in a `CyclicGame`, by its position round the circle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Move(usize);
+
+impl Move {
+    const ROCK: Move = Move(0);
+    const PAPER: Move = Move(1);
+    const SCISSORS: Move = Move(2);
+
+    /// The move `places` round from this one in a circle of `n` moves.
+    fn shift(self, places: isize, n: usize) -> Move {
+        Move((self.0 as isize + places).rem_euclid(n as isize) as usize)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Outcome {
+    Loss,
+    Draw,
+    Win,
+}
+
+impl Outcome {
+    /// A move that ends in this outcome against `them` in a game of `n` moves.
+    fn against(self, them: Move, n: usize) -> Move {
+        match self {
+            Outcome::Loss => them.shift(-1, n),
+            Outcome::Draw => them,
+            Outcome::Win => them.shift(1, n),
+        }
+    }
+}
+
+/// Rock-paper-scissors with any odd number of moves sat in a circle, where each move beats
+/// the ones an odd number of places behind it and loses to the rest. With Spock and lizard added
+/// after scissors this gives the usual five-move rules.
+struct CyclicGame {
+    move_points: Vec<u32>,
+    loss: u32,
+    draw: u32,
+    win: u32,
+}
+
+impl CyclicGame {
+    fn new(move_points: Vec<u32>, loss: u32, draw: u32, win: u32) -> Result<Self, String> {
+        if move_points.len().is_multiple_of(2) {
+            return Err(format!(
+                "a fair game needs an odd number of moves, not {}",
+                move_points.len()
+            ));
+        }
+        Ok(CyclicGame {
+            move_points,
+            loss,
+            draw,
+            win,
+        })
+    }
+
+    fn rock_paper_scissors() -> Self {
+        CyclicGame::new(vec![1, 2, 3], 0, 3, 6).unwrap()
+    }
+
+    fn moves(&self) -> usize {
+        self.move_points.len()
+    }
+
+    fn outcome(&self, me: Move, them: Move) -> Outcome {
+        match (me.0 + self.moves() - them.0) % self.moves() {
+            0 => Outcome::Draw,
+            places if places % 2 == 1 => Outcome::Win,
+            _ => Outcome::Loss,
+        }
+    }
+
+    fn score_round(&self, round: Round) -> u32 {
+        let outcome_points = match self.outcome(round.me, round.them) {
+            Outcome::Loss => self.loss,
+            Outcome::Draw => self.draw,
+            Outcome::Win => self.win,
+        };
+        self.move_points[round.me.0] + outcome_points
+    }
+}
+
+#[rstest]
+#[case::rock_crushes_scissors(3, Move::ROCK, Move::SCISSORS, Outcome::Win)]
+#[case::paper_covers_rock(3, Move::ROCK, Move::PAPER, Outcome::Loss)]
+#[case::scissors_tie(3, Move::SCISSORS, Move::SCISSORS, Outcome::Draw)]
+#[case::spock_smashes_scissors(5, Move(3), Move::SCISSORS, Outcome::Win)]
+#[case::spock_vaporizes_rock(5, Move(3), Move::ROCK, Outcome::Win)]
+#[case::paper_disproves_spock(5, Move(3), Move::PAPER, Outcome::Loss)]
+#[case::lizard_eats_paper(5, Move(4), Move::PAPER, Outcome::Win)]
+#[case::rock_crushes_lizard(5, Move(4), Move::ROCK, Outcome::Loss)]
+fn test_outcome(#[case] n: usize, #[case] me: Move, #[case] them: Move, #[case] result: Outcome) {
+    let game = CyclicGame::new(vec![0; n], 0, 0, 0).unwrap();
+    assert_eq!(game.outcome(me, them), result);
+}
+
+#[test]
+fn test_against() {
+    for n in [3, 5, 7] {
+        let game = CyclicGame::new(vec![0; n], 0, 0, 0).unwrap();
+        for them in (0..n).map(Move) {
+            for outcome in [Outcome::Loss, Outcome::Draw, Outcome::Win] {
+                assert_eq!(game.outcome(outcome.against(them, n), them), outcome);
+            }
+        }
+    }
+    assert_eq!(
+        CyclicGame::new(vec![1, 2], 0, 3, 6).err(),
+        Some("a fair game needs an odd number of moves, not 2".to_string())
+    );
 }
 
 fn decrypt_their_move(a: char) -> Result<Move, &'static str> {
     match a {
-        'A' => Ok(Move::Rock),
-        'B' => Ok(Move::Paper),
-        'C' => Ok(Move::Scissors),
+        'A' => Ok(Move::ROCK),
+        'B' => Ok(Move::PAPER),
+        'C' => Ok(Move::SCISSORS),
         _ => Err("Invalid opponent move"),
     }
 }
 
 fn decrypt_my_move(a: char, _: &Move) -> Result<Move, &'static str> {
     match a {
-        'X' => Ok(Move::Rock),
-        'Y' => Ok(Move::Paper),
-        'Z' => Ok(Move::Scissors),
+        'X' => Ok(Move::ROCK),
+        'Y' => Ok(Move::PAPER),
+        'Z' => Ok(Move::SCISSORS),
         _ => Err("Invalid player move"),
     }
 }
 
 fn decrypt_my_move_differently(a: char, them: &Move) -> Result<Move, &'static str> {
-    match a {
-        'X' => match them {
-            Move::Rock => Ok(Move::Scissors),
-            Move::Paper => Ok(Move::Rock),
-            Move::Scissors => Ok(Move::Paper),
-        },
-        'Y' => Ok(*them),
-        'Z' => match them {
-            Move::Rock => Ok(Move::Paper),
-            Move::Paper => Ok(Move::Scissors),
-            Move::Scissors => Ok(Move::Rock),
-        },
-        _ => Err("Invalid player move"),
-    }
+    let outcome = match a {
+        'X' => Outcome::Loss,
+        'Y' => Outcome::Draw,
+        'Z' => Outcome::Win,
+        _ => return Err("Invalid player move"),
+    };
+    Ok(outcome.against(*them, 3))
 }
 
 struct Round {
@@ -51,14 +153,14 @@ struct Round {
     them: Move,
 }
 
-struct TournamentInput {
+struct TournamentInput<'a> {
     lines: io::Lines<io::BufReader<File>>,
-    decryptor: MyMoveDecryptor,
+    decryptor: MyMoveDecryptor<'a>,
 }
 
-type MyMoveDecryptor = fn(char, &Move) -> Result<Move, &'static str>;
+type MyMoveDecryptor<'a> = &'a dyn Fn(char, &Move) -> Result<Move, &'static str>;
 
-impl Iterator for TournamentInput {
+impl Iterator for TournamentInput<'_> {
     type Item = Round;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -81,48 +183,177 @@ impl Iterator for TournamentInput {
     }
 }
 
-fn score_round(round: Round) -> u32 {
-    let move_points = match round.me {
-        Move::Rock => 1,
-        Move::Paper => 2,
-        Move::Scissors => 3,
-    };
-    let win_points = match (round.me, round.them) {
-        (Move::Rock, Move::Paper) => 0,
-        (Move::Paper, Move::Scissors) => 0,
-        (Move::Scissors, Move::Rock) => 0,
-
-        (Move::Rock, Move::Rock) => 3,
-        (Move::Paper, Move::Paper) => 3,
-        (Move::Scissors, Move::Scissors) => 3,
-
-        (Move::Rock, Move::Scissors) => 6,
-        (Move::Paper, Move::Rock) => 6,
-        (Move::Scissors, Move::Paper) => 6,
-    };
-    move_points + win_points
-}
-
 #[rstest]
-#[case::rock_loss(Round{me: Move::Rock, them: Move::Paper}, 1)]
-#[case::paper_tie(Round{me: Move::Paper, them: Move::Paper}, 5)]
-#[case::scissors_win(Round{me: Move::Scissors, them: Move::Paper}, 9)]
+#[case::rock_loss(Round{me: Move::ROCK, them: Move::PAPER}, 1)]
+#[case::paper_tie(Round{me: Move::PAPER, them: Move::PAPER}, 5)]
+#[case::scissors_win(Round{me: Move::SCISSORS, them: Move::PAPER}, 9)]
 fn test_score_round(#[case] round: Round, #[case] score: u32) {
-    assert_eq!(score_round(round), score);
+    assert_eq!(CyclicGame::rock_paper_scissors().score_round(round), score);
 }
 
-fn process_tournament(filename: &str, decryptor: MyMoveDecryptor) -> u32 {
+fn process_tournament(filename: &str, game: &CyclicGame, decryptor: MyMoveDecryptor) -> u32 {
     let lines = fs::read_lines(filename).unwrap();
     let tournament = TournamentInput { lines, decryptor };
     let mut total_score = 0;
     for round in tournament {
-        total_score += score_round(round);
+        total_score += game.score_round(round);
     }
     total_score
 }
 
+/// Scores the tournament under both readings of the strategy guide, with custom points for
+/// playing rock, paper and scissors and for losing, drawing and winning.
+pub fn score(filename: &str, move_points: &str, outcome_points: &str) -> String {
+    let parse =
+        |points: &str| -> Vec<u32> { points.split(',').map(|p| p.parse().unwrap()).collect() };
+    let move_points = parse(move_points);
+    assert_eq!(
+        move_points.len(),
+        3,
+        "expected points for rock, paper and scissors"
+    );
+    let [loss, draw, win] = parse(outcome_points)[..] else {
+        panic!("expected points for a loss, a draw and a win");
+    };
+    let game = CyclicGame::new(move_points, loss, draw, win).unwrap();
+    format!(
+        "as moves: {}\nas outcomes: {}\n",
+        process_tournament(filename, &game, &decrypt_my_move),
+        process_tournament(filename, &game, &decrypt_my_move_differently)
+    )
+}
+
+#[test]
+fn test_score() {
+    assert_eq!(
+        score("./test02.txt", "1,2,3", "0,3,6"),
+        "as moves: 15\nas outcomes: 12\n"
+    );
+    assert_eq!(
+        score("./test02.txt", "0,0,0", "0,1,2"),
+        "as moves: 3\nas outcomes: 3\n"
+    );
+}
+
+/// One way of reading the second column of the strategy guide: either as the moves to play or as
+/// the outcomes to aim for.
+#[derive(Debug, PartialEq)]
+enum Reading {
+    Moves([Move; 3]),
+    Outcomes([Outcome; 3]),
+}
+
+impl Reading {
+    /// Every way of matching X, Y and Z up with the three moves or the three outcomes.
+    fn all() -> Vec<Reading> {
+        let moves = [Move::ROCK, Move::PAPER, Move::SCISSORS];
+        let outcomes = [Outcome::Loss, Outcome::Draw, Outcome::Win];
+        let moves = moves
+            .into_iter()
+            .permutations(3)
+            .map(|p| Reading::Moves([p[0], p[1], p[2]]));
+        let outcomes = outcomes
+            .into_iter()
+            .permutations(3)
+            .map(|p| Reading::Outcomes([p[0], p[1], p[2]]));
+        moves.chain(outcomes).collect()
+    }
+
+    fn decrypt(&self, a: char, them: &Move) -> Result<Move, &'static str> {
+        let i = match a {
+            'X' => 0,
+            'Y' => 1,
+            'Z' => 2,
+            _ => return Err("Invalid player move"),
+        };
+        match self {
+            Reading::Moves(moves) => Ok(moves[i]),
+            Reading::Outcomes(outcomes) => Ok(outcomes[i].against(*them, 3)),
+        }
+    }
+}
+
+impl std::fmt::Display for Reading {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<&str> = match self {
+            Reading::Moves(moves) => moves
+                .iter()
+                .map(|m| ["rock", "paper", "scissors"][m.0])
+                .collect(),
+            Reading::Outcomes(outcomes) => outcomes
+                .iter()
+                .map(|o| match o {
+                    Outcome::Loss => "lose",
+                    Outcome::Draw => "draw",
+                    Outcome::Win => "win",
+                })
+                .collect(),
+        };
+        write!(f, "X={} Y={} Z={}", names[0], names[1], names[2])
+    }
+}
+
+#[test]
+fn test_reading_all() {
+    let readings = Reading::all();
+    assert_eq!(readings.len(), 12);
+    assert_eq!(
+        readings[0],
+        Reading::Moves([Move::ROCK, Move::PAPER, Move::SCISSORS])
+    );
+    assert_eq!(readings[6].to_string(), "X=lose Y=draw Z=win".to_string());
+}
+
+/// Scores the tournament under every reading of the strategy guide, best first. Readings that
+/// score the same keep the order `Reading::all` gives them.
+fn score_readings(filename: &str) -> Vec<(u32, Reading)> {
+    let game = CyclicGame::rock_paper_scissors();
+    let mut scores: Vec<(u32, Reading)> = Reading::all()
+        .into_iter()
+        .map(|reading| {
+            let decryptor = |a, them: &Move| reading.decrypt(a, them);
+            (process_tournament(filename, &game, &decryptor), reading)
+        })
+        .collect();
+    scores.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+    scores
+}
+
+#[test]
+fn test_score_readings() {
+    let scores = score_readings("./test02.txt");
+    assert!(scores.contains(&(15, Reading::all().remove(0))));
+    assert!(scores.contains(&(12, Reading::all().remove(6))));
+    // Every round is a win if X is scissors, Y paper and Z rock
+    assert_eq!(
+        scores.first(),
+        Some(&(
+            24,
+            Reading::Moves([Move::SCISSORS, Move::PAPER, Move::ROCK])
+        ))
+    );
+}
+
+/// The best and worst ways of reading the strategy guide, followed by every reading ranked.
+pub fn guide(filename: &str) -> String {
+    let scores = score_readings(filename);
+    let (best, worst) = (scores.first().unwrap(), scores.last().unwrap());
+    let mut result = format!(
+        "best: {} ({})\nworst: {} ({})\n\n",
+        best.1, best.0, worst.1, worst.0
+    );
+    for (score, reading) in &scores {
+        result.push_str(&format!("{:>6} {}\n", score, reading));
+    }
+    result
+}
+
 pub fn part_1(filename: &str) -> u32 {
-    process_tournament(filename, decrypt_my_move)
+    process_tournament(
+        filename,
+        &CyclicGame::rock_paper_scissors(),
+        &decrypt_my_move,
+    )
 }
 
 #[rstest]
@@ -133,7 +364,11 @@ fn test_part_1(#[case] filename: &str, #[case] result: u32) {
 }
 
 pub fn part_2(filename: &str) -> u32 {
-    process_tournament(filename, decrypt_my_move_differently)
+    process_tournament(
+        filename,
+        &CyclicGame::rock_paper_scissors(),
+        &decrypt_my_move_differently,
+    )
 }
 
 #[rstest]
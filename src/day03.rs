@@ -1,7 +1,10 @@
 use std::{collections::HashSet, fs::File, io};
 
+use itertools::Itertools;
 use rstest::rstest;
+use serde_json::json;
 
+use crate::bench;
 use crate::fs;
 
 #[derive(Default)]
@@ -59,7 +62,7 @@ fn test_get_priority(#[case] item: char, #[case] priority: u32) {
     assert_eq!(get_priority(item).unwrap(), priority);
 }
 
-pub fn part_1(filename: &str) -> u32 {
+fn part_1_hashset(filename: &str) -> u32 {
     let lines = fs::read_lines(filename).unwrap();
     let rucksacks_input = RucksacksInput { lines };
     let mut total_priority = 0;
@@ -75,14 +78,7 @@ pub fn part_1(filename: &str) -> u32 {
     total_priority
 }
 
-#[rstest]
-#[case::test("./test03.txt", 157)]
-#[case::input("./input03.txt", 7917)]
-fn test_part_1(#[case] filename: &str, #[case] result: u32) {
-    assert_eq!(part_1(filename), result);
-}
-
-pub fn part_2(filename: &str) -> u32 {
+fn part_2_hashset(filename: &str) -> u32 {
     let lines = fs::read_lines(filename).unwrap();
     let rucksacks_input = RucksacksInput { lines };
     let mut total_priority = 0;
@@ -114,9 +110,349 @@ pub fn part_2(filename: &str) -> u32 {
     total_priority
 }
 
+#[rstest]
+#[case::test("./test03.txt", 157, 70)]
+#[case::input("./input03.txt", 7917, 2585)]
+fn test_hashset(#[case] filename: &str, #[case] part_1: u32, #[case] part_2: u32) {
+    assert_eq!(part_1_hashset(filename), part_1);
+    assert_eq!(part_2_hashset(filename), part_2);
+}
+
+/// A set of rucksack items, with a bit for each priority from 1 to 52.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ItemSet(u64);
+
+impl ItemSet {
+    const EMPTY: ItemSet = ItemSet(0);
+    const ALL: ItemSet = ItemSet(((1 << 52) - 1) << 1);
+
+    fn from_items(items: &str) -> Result<ItemSet, &'static str> {
+        items.chars().try_fold(ItemSet::EMPTY, |set, item| {
+            Ok(set.union(ItemSet(1 << get_priority(item)?)))
+        })
+    }
+
+    fn intersection(self, other: ItemSet) -> ItemSet {
+        ItemSet(self.0 & other.0)
+    }
+
+    fn union(self, other: ItemSet) -> ItemSet {
+        ItemSet(self.0 | other.0)
+    }
+
+    /// The priorities of the items in the set, lowest first.
+    fn iter(self) -> impl Iterator<Item = u32> {
+        let mut bits = self.0;
+        std::iter::from_fn(move || match bits {
+            0 => None,
+            _ => {
+                let priority = bits.trailing_zeros();
+                bits &= bits - 1;
+                Some(priority)
+            }
+        })
+    }
+}
+
+#[test]
+fn test_item_set() {
+    let left = ItemSet::from_items("vJrwpWtwJgWr").unwrap();
+    let right = ItemSet::from_items("hcsFMMfFFhFp").unwrap();
+    assert_eq!(
+        left.intersection(right).iter().collect::<Vec<u32>>(),
+        vec![16]
+    );
+    assert_eq!(left.iter().count(), 8);
+    assert_eq!(left.union(right).iter().count(), 14);
+    assert_eq!(
+        ItemSet::ALL.iter().collect::<Vec<u32>>(),
+        (1..=52).collect::<Vec<u32>>()
+    );
+    assert_eq!(
+        ItemSet::from_items("aZ")
+            .unwrap()
+            .iter()
+            .collect::<Vec<u32>>(),
+        vec![1, 52]
+    );
+    assert_eq!(ItemSet::from_items("ab1"), Err("Invalid rucksack item"));
+}
+
+fn priority_item(priority: u32) -> char {
+    match priority {
+        1..=26 => (b'a' + priority as u8 - 1) as char,
+        _ => (b'A' + priority as u8 - 27) as char,
+    }
+}
+
+fn items(set: ItemSet) -> String {
+    set.iter().map(priority_item).collect()
+}
+
+/// Each rucksack's two compartments, as read from a file.
+fn compartments_from_file(filename: &str) -> impl Iterator<Item = (ItemSet, ItemSet)> {
+    fs::read_lines(filename).unwrap().map(|line| {
+        let line = line.unwrap();
+        let (first, second) = line.split_at(line.len() / 2);
+        (
+            ItemSet::from_items(first).unwrap(),
+            ItemSet::from_items(second).unwrap(),
+        )
+    })
+}
+
+pub fn part_1(filename: &str) -> u32 {
+    compartments_from_file(filename)
+        .map(|(first, second)| first.intersection(second).iter().sum::<u32>())
+        .sum()
+}
+
+#[rstest]
+#[case::test("./test03.txt", 157)]
+#[case::input("./input03.txt", 7917)]
+fn test_part_1(#[case] filename: &str, #[case] result: u32) {
+    assert_eq!(part_1(filename), result);
+}
+
+/// The total priority of the items each group of `group_size` elves has in common, taking the
+/// rucksacks in order. With groups of three this is the priority of each group's badge.
+pub fn badges(filename: &str, group_size: usize) -> u32 {
+    assert!(group_size > 0, "Groups need at least one elf");
+    compartments_from_file(filename)
+        .map(|(first, second)| first.union(second))
+        .chunks(group_size)
+        .into_iter()
+        .map(|group| {
+            group
+                .fold(ItemSet::ALL, ItemSet::intersection)
+                .iter()
+                .sum::<u32>()
+        })
+        .sum()
+}
+
+#[rstest]
+#[case(1, 2278)]
+#[case(2, 371)]
+#[case(6, 0)]
+#[should_panic(expected = "Groups need at least one elf")]
+#[case(0, 0)]
+fn test_badges(#[case] group_size: usize, #[case] result: u32) {
+    assert_eq!(badges("./test03.txt", group_size), result);
+}
+
+pub fn part_2(filename: &str) -> u32 {
+    badges(filename, 3)
+}
+
 #[rstest]
 #[case::test("./test03.txt", 70)]
 #[case::input("./input03.txt", 2585)]
 fn test_part_2(#[case] filename: &str, #[case] result: u32) {
     assert_eq!(part_2(filename), result);
 }
+
+/// Everything unexpected in a list of rucksacks. Line numbers start from 1.
+#[derive(Debug, Default, PartialEq)]
+struct Diagnostics {
+    /// The items found in both compartments of each rucksack that has any
+    shared: Vec<(usize, String)>,
+    /// The first and last line of each group that doesn't have exactly one item in common, and
+    /// the items it does have in common
+    badges: Vec<((usize, usize), String)>,
+    /// Lines that can't be split evenly, and their lengths in characters
+    odd_length: Vec<(usize, usize)>,
+    /// Lines holding something other than letters, and the first offending character
+    invalid: Vec<(usize, char)>,
+}
+
+/// Checks every rucksack rather than stopping at the first problem. Anything that isn't an item
+/// is left out of the rucksack it's in.
+fn diagnose(filename: &str, group_size: usize) -> Diagnostics {
+    assert!(group_size > 0, "Groups need at least one elf");
+    let mut diagnostics = Diagnostics::default();
+    let mut rucksacks = Vec::new();
+    for (i, line) in fs::read_lines(filename).unwrap().enumerate() {
+        let (line_number, line) = (i + 1, line.unwrap());
+        let length = line.chars().count();
+        if length % 2 == 1 {
+            diagnostics.odd_length.push((line_number, length));
+        }
+        if let Some(c) = line.chars().find(|&c| get_priority(c).is_err()) {
+            diagnostics.invalid.push((line_number, c));
+        }
+        let valid_items = |items: &str| {
+            ItemSet::from_items(&items.replace(|c| get_priority(c).is_err(), "")).unwrap()
+        };
+        // Split by character rather than byte, so that non-ASCII junk can't land mid-character
+        let middle = line
+            .char_indices()
+            .nth(length / 2)
+            .map_or(line.len(), |(i, _)| i);
+        let (first, second) = line.split_at(middle);
+        let (first, second) = (valid_items(first), valid_items(second));
+        let shared = first.intersection(second);
+        if shared != ItemSet::EMPTY {
+            diagnostics.shared.push((line_number, items(shared)));
+        }
+        rucksacks.push(first.union(second));
+    }
+    for (i, group) in rucksacks.chunks(group_size).enumerate() {
+        let common = group.iter().fold(ItemSet::ALL, |common, &rucksack| {
+            common.intersection(rucksack)
+        });
+        if common.iter().count() != 1 {
+            let first_line = i * group_size + 1;
+            let lines = (first_line, first_line + group.len() - 1);
+            diagnostics.badges.push((lines, items(common)));
+        }
+    }
+    diagnostics
+}
+
+#[test]
+fn test_diagnose() {
+    let clean = diagnose("./test03.txt", 3);
+    assert_eq!(clean.shared.len(), 6);
+    assert_eq!(clean.shared[0], (1, "p".to_string()));
+    assert_eq!(
+        (clean.badges, clean.odd_length, clean.invalid),
+        (vec![], vec![], vec![])
+    );
+    assert_eq!(
+        diagnose("./test03-2.txt", 3),
+        Diagnostics {
+            shared: vec![
+                (1, "a".to_string()),
+                (3, "aZ".to_string()),
+                (4, "xy".to_string())
+            ],
+            badges: vec![
+                ((1, 3), "ab".to_string()),
+                ((4, 6), "".to_string()),
+                ((7, 7), "ab".to_string())
+            ],
+            odd_length: vec![(3, 5), (4, 5), (7, 3)],
+            invalid: vec![(4, '1'), (7, 'é')],
+        }
+    );
+}
+
+impl Diagnostics {
+    fn to_text(&self) -> String {
+        let mut text = String::new();
+        let mut section = |title: &str, lines: Vec<String>| {
+            text.push_str(&format!("{}:\n", title));
+            if lines.is_empty() {
+                text.push_str("  none\n");
+            }
+            for line in lines {
+                text.push_str(&format!("  {}\n", line));
+            }
+        };
+        section(
+            "shared items",
+            self.shared
+                .iter()
+                .map(|(line, items)| format!("line {}: {}", line, items))
+                .collect(),
+        );
+        section(
+            "badge anomalies",
+            self.badges
+                .iter()
+                .map(|((first, last), items)| match items.len() {
+                    0 => format!("lines {}-{}: no common item", first, last),
+                    _ => format!("lines {}-{}: {}", first, last, items),
+                })
+                .collect(),
+        );
+        section(
+            "odd-length lines",
+            self.odd_length
+                .iter()
+                .map(|(line, length)| format!("line {}: {} items", line, length))
+                .collect(),
+        );
+        section(
+            "invalid lines",
+            self.invalid
+                .iter()
+                .map(|(line, c)| format!("line {}: '{}'", line, c))
+                .collect(),
+        );
+        text
+    }
+
+    fn to_json(&self) -> String {
+        json!({
+            "shared": self.shared.iter().map(|(line, items)| json!({
+                "line": line,
+                "items": items,
+            })).collect::<Vec<_>>(),
+            "badges": self.badges.iter().map(|((first, last), items)| json!({
+                "lines": [first, last],
+                "candidates": items,
+            })).collect::<Vec<_>>(),
+            "odd_length": self.odd_length.iter().map(|(line, length)| json!({
+                "line": line,
+                "length": length,
+            })).collect::<Vec<_>>(),
+            "invalid": self.invalid.iter().map(|(line, c)| json!({
+                "line": line,
+                "item": c,
+            })).collect::<Vec<_>>(),
+        })
+        .to_string()
+    }
+}
+
+/// Reports every problem with the rucksacks, as `text` or `json`.
+pub fn check(filename: &str, format: &str, group_size: usize) -> String {
+    let diagnostics = diagnose(filename, group_size);
+    match format {
+        "text" => diagnostics.to_text(),
+        "json" => diagnostics.to_json() + "\n",
+        _ => panic!("Unknown report format {}", format),
+    }
+}
+
+#[test]
+fn test_check() {
+    assert_eq!(
+        check("./test03-2.txt", "text", 3),
+        "shared items:\n\
+         \x20 line 1: a\n\
+         \x20 line 3: aZ\n\
+         \x20 line 4: xy\n\
+         badge anomalies:\n\
+         \x20 lines 1-3: ab\n\
+         \x20 lines 4-6: no common item\n\
+         \x20 lines 7-7: ab\n\
+         odd-length lines:\n\
+         \x20 line 3: 5 items\n\
+         \x20 line 4: 5 items\n\
+         \x20 line 7: 3 items\n\
+         invalid lines:\n\
+         \x20 line 4: '1'\n\
+         \x20 line 7: 'é'\n"
+    );
+    assert_eq!(
+        check("./test03-2.txt", "json", 3),
+        r#"{"badges":[{"candidates":"ab","lines":[1,3]},{"candidates":"","lines":[4,6]},{"candidates":"ab","lines":[7,7]}],"invalid":[{"item":"1","line":4},{"item":"é","line":7}],"odd_length":[{"length":5,"line":3},{"length":5,"line":4},{"length":3,"line":7}],"shared":[{"items":"a","line":1},{"items":"aZ","line":3},{"items":"xy","line":4}]}"#.to_string() + "\n"
+    );
+}
+
+#[test]
+#[should_panic(expected = "Groups need at least one elf")]
+fn test_check_empty_groups() {
+    check("./test03-2.txt", "text", 0);
+}
+
+/// Times the bitset rucksacks against the original `HashSet` ones.
+pub fn bench(filename: &str) {
+    bench::bench("part 1: HashSet", 200, || part_1_hashset(filename));
+    bench::bench("part 1: ItemSet", 200, || part_1(filename));
+    bench::bench("part 2: HashSet", 200, || part_2_hashset(filename));
+    bench::bench("part 2: ItemSet", 200, || part_2(filename));
+}
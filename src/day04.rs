@@ -6,6 +6,7 @@ use std::{
 use rstest::rstest;
 
 use crate::fs;
+use crate::interval_set::IntervalSet;
 
 struct AssignmentPairsInput<B> {
     lines: io::Lines<B>,
@@ -45,8 +46,8 @@ fn test_assignment_pairs_input(#[case] lines_str: String, #[case] expected: Vec<
 }
 
 fn one_contains_other(pair: AssignmentPair) -> bool {
-    (pair.0.contains(&pair.1.start()) && pair.0.contains(&pair.1.end()))
-        || (pair.1.contains(&pair.0.start()) && pair.1.contains(&pair.0.end()))
+    let (left, right) = (IntervalSet::from(pair.0), IntervalSet::from(pair.1));
+    left.subtract(&right).is_empty() || right.subtract(&left).is_empty()
 }
 
 #[rstest]
@@ -79,10 +80,9 @@ fn test_part_1(#[case] filename: &str, #[case] result: u32) {
 }
 
 fn overlaps(pair: AssignmentPair) -> bool {
-    pair.0.contains(pair.1.start())
-        || pair.0.contains(pair.1.end())
-        || pair.1.contains(pair.0.start())
-        || pair.1.contains(pair.0.end())
+    !IntervalSet::from(pair.0)
+        .intersect(&IntervalSet::from(pair.1))
+        .is_empty()
 }
 
 #[rstest]
@@ -114,3 +114,196 @@ pub fn part_2(filename: &str) -> u32 {
 fn test_part_2(#[case] filename: &str, #[case] result: u32) {
     assert_eq!(part_2(filename), result);
 }
+
+/// How many sections the elves cover between them and where the gaps are, how many sections are
+/// given to both elves of some pair, and which sections at least `k` elves have been assigned to.
+pub fn coverage(filename: &str, k: usize) -> String {
+    let pairs: Vec<AssignmentPair> = AssignmentPairsInput {
+        lines: fs::read_lines(filename).unwrap(),
+    }
+    .collect();
+    let lefts: IntervalSet = pairs.iter().map(|(left, _)| left.clone()).collect();
+    let rights: IntervalSet = pairs.iter().map(|(_, right)| right.clone()).collect();
+    let covered = lefts.union(&rights);
+    let mut doubled_up = IntervalSet::new();
+    for (left, right) in &pairs {
+        // Empty when the pair doesn't overlap, which `insert` ignores
+        doubled_up.insert(*left.start().max(right.start())..=*left.end().min(right.end()));
+    }
+    let gaps = match (covered.ranges().first(), covered.ranges().last()) {
+        (Some(first), Some(last)) => {
+            IntervalSet::from(*first.start()..=*last.end()).subtract(&covered)
+        }
+        _ => IntervalSet::new(),
+    };
+    let crowded = IntervalSet::covered_by_at_least(
+        pairs.into_iter().flat_map(|(left, right)| [left, right]),
+        k,
+    );
+    format!(
+        "covered: {} sections, gaps {:?}\n\
+         within a pair: {} sections\n\
+         by at least {} elves: {} sections in {:?}\n",
+        covered.coverage(),
+        gaps.ranges(),
+        doubled_up.coverage(),
+        k,
+        crowded.coverage(),
+        crowded.ranges(),
+    )
+}
+
+#[test]
+fn test_coverage() {
+    assert_eq!(
+        coverage("./test04.txt", 3),
+        "covered: 8 sections, gaps []\n\
+         within a pair: 5 sections\n\
+         by at least 3 elves: 7 sections in [2..=8]\n"
+    );
+}
+
+/// Sweeps across every range in order and returns the most ranges any one number falls within,
+/// and the numbers where that happens.
+fn deepest_overlap<I>(ranges: I) -> (usize, IntervalSet)
+where
+    I: IntoIterator<Item = RangeInclusive<u32>>,
+{
+    let mut events: Vec<(u64, isize)> = ranges
+        .into_iter()
+        .filter(|r| !r.is_empty())
+        .flat_map(|r| [(*r.start() as u64, 1), (*r.end() as u64 + 1, -1)])
+        .collect();
+    events.sort_unstable();
+    let (mut open, mut deepest) = (0, 0);
+    let mut deepest_at = Vec::new();
+    for window in events.windows(2) {
+        let ((at, change), (next, _)) = (window[0], window[1]);
+        open += change;
+        if at == next {
+            continue;
+        }
+        if open > deepest {
+            deepest = open;
+            deepest_at.clear();
+        }
+        if open == deepest {
+            deepest_at.push(at as u32..=(next - 1) as u32);
+        }
+    }
+    (deepest as usize, deepest_at.into_iter().collect())
+}
+
+#[rstest]
+#[case(vec![], 0, vec![])]
+#[case(vec![1..=5, 3..=8, 4..=4, 7..=10], 3, vec![4..=4])]
+#[case(vec![1..=2, 3..=4, 6..=6], 1, vec![1..=4, 6..=6])]
+#[case(vec![1..=5, 5..=6, 2..=3, 6..=9], 2, vec![2..=3, 5..=6])]
+#[allow(clippy::reversed_empty_ranges)]
+#[case(vec![1..=10, 6..=3], 1, vec![1..=10])]
+fn test_deepest_overlap(
+    #[case] ranges: Vec<RangeInclusive<u32>>,
+    #[case] depth: usize,
+    #[case] at: Vec<RangeInclusive<u32>>,
+) {
+    let (deepest, deepest_at) = deepest_overlap(ranges);
+    assert_eq!((deepest, deepest_at.ranges()), (depth, &at[..]));
+}
+
+/// Elves are identified by the line their pair is on, counting from 1, and which of the two they
+/// are.
+type ElfId = (usize, usize);
+
+#[derive(Debug, PartialEq)]
+struct CrossPairAnalysis {
+    /// Sections assigned to more than one pair
+    shared_between_pairs: IntervalSet,
+    /// The most elves assigned to any one section, and the sections with that many
+    busiest: (usize, IntervalSet),
+    /// Elves whose every section is also assigned to someone else. Any one of them could be let
+    /// off, though not necessarily all at once.
+    redundant: Vec<(ElfId, RangeInclusive<u32>)>,
+}
+
+fn analyse_pairs<I>(pairs: I) -> CrossPairAnalysis
+where
+    I: IntoIterator<Item = AssignmentPair>,
+{
+    let elves: Vec<(ElfId, RangeInclusive<u32>)> = pairs
+        .into_iter()
+        .enumerate()
+        .flat_map(|(i, (left, right))| [((i + 1, 1), left), ((i + 1, 2), right)])
+        .collect();
+    // Both elves of a pair covering a section only counts once towards it being shared
+    let pair_sections = elves.chunks(2).flat_map(|pair| {
+        let sections: IntervalSet = pair.iter().map(|(_, range)| range.clone()).collect();
+        sections.ranges().to_vec()
+    });
+    let shared_between_pairs = IntervalSet::covered_by_at_least(pair_sections, 2);
+    let ranges = || elves.iter().map(|(_, range)| range.clone());
+    let busiest = deepest_overlap(ranges());
+    let doubled_up = IntervalSet::covered_by_at_least(ranges(), 2);
+    let redundant = elves
+        .iter()
+        .filter(|(_, range)| doubled_up.contains_range(range))
+        .cloned()
+        .collect();
+    CrossPairAnalysis {
+        shared_between_pairs,
+        busiest,
+        redundant,
+    }
+}
+
+#[test]
+fn test_analyse_pairs() {
+    let pairs = AssignmentPairsInput {
+        lines: fs::read_lines("./test04.txt").unwrap(),
+    };
+    let analysis = analyse_pairs(pairs);
+    assert_eq!(analysis.shared_between_pairs.ranges(), [2..=8]);
+    assert_eq!(analysis.busiest, (8, IntervalSet::from(6..=6)));
+    assert_eq!(analysis.redundant.len(), 11);
+    assert!(!analysis.redundant.contains(&((3, 2), 7..=9)));
+
+    let pairs = [(1..=3, 10..=12), (2..=2, 20..=20), (11..=12, 1..=1)];
+    let analysis = analyse_pairs(pairs);
+    assert_eq!(analysis.shared_between_pairs.ranges(), [1..=2, 11..=12]);
+    assert_eq!(
+        analysis.busiest,
+        (2, [1..=2, 11..=12].into_iter().collect())
+    );
+    assert_eq!(
+        analysis.redundant,
+        vec![((2, 1), 2..=2), ((3, 1), 11..=12), ((3, 2), 1..=1)]
+    );
+}
+
+/// Looks across all the pairs at once: which sections more than one pair is working on, where the
+/// most elves are crowded together, and which elves nobody would miss.
+pub fn cross_pairs(filename: &str) -> String {
+    let analysis = analyse_pairs(AssignmentPairsInput {
+        lines: fs::read_lines(filename).unwrap(),
+    });
+    let (deepest, deepest_at) = &analysis.busiest;
+    let mut result = format!(
+        "shared between pairs: {} sections in {:?}\n\
+         busiest: {} elves in {:?}\n\
+         redundant elves: {}\n",
+        analysis.shared_between_pairs.coverage(),
+        analysis.shared_between_pairs.ranges(),
+        deepest,
+        deepest_at.ranges(),
+        analysis.redundant.len()
+    );
+    for ((line, elf), range) in &analysis.redundant {
+        result.push_str(&format!(
+            "  line {} elf {}: {}-{}\n",
+            line,
+            elf,
+            range.start(),
+            range.end()
+        ));
+    }
+    result
+}
@@ -1,44 +1,372 @@
-use std::collections::VecDeque;
+use std::{collections::VecDeque, fmt, str::FromStr};
 
 use rstest::rstest;
 
 use crate::fs;
 
-pub fn part_1(filename: &str) -> String {
-    let mut stacks: Vec<VecDeque<char>> = Vec::new();
-    let mut lines = fs::read_lines(filename).unwrap().filter_map(|s| s.ok());
-    let crate_section = lines.by_ref().take_while(|s| s != "");
-    for layer in crate_section {
-        if layer == String::from("") {
-            break;
-        }
-        for (i, doof) in layer.as_bytes().chunks(4).enumerate() {
-            if stacks.len() <= i {
-                stacks.insert(i, VecDeque::new());
+/// Numbered stacks of crates, each listed from the top down.
+#[derive(Clone, Debug, PartialEq)]
+struct Stacks(Vec<VecDeque<char>>);
+
+impl Stacks {
+    /// Reads the drawing of the stacks, stopping at the first blank line. The row of stack
+    /// numbers underneath is skipped along with anything else that isn't a crate.
+    fn parse<I>(lines: I) -> Stacks
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut stacks: Vec<VecDeque<char>> = Vec::new();
+        for layer in lines.into_iter().take_while(|s| !s.is_empty()) {
+            for (i, slot) in layer.as_bytes().chunks(4).enumerate() {
+                if stacks.len() <= i {
+                    stacks.push(VecDeque::new());
+                }
+                if slot[0] == b'[' {
+                    stacks[i].push_back(slot[1] as char);
+                }
             }
-            if doof[0] == '[' as u8 {
-                stacks[i].push_back(doof[1] as char);
+        }
+        Stacks(stacks)
+    }
+
+    /// The crate on top of each stack, or a space for an empty one.
+    fn tops(&self) -> String {
+        self.0
+            .iter()
+            .map(|stack| stack.front().copied().unwrap_or(' '))
+            .collect()
+    }
+
+    /// Draws the stacks the way the puzzle does, with the stack numbers underneath.
+    fn render(&self) -> String {
+        let height = self.0.iter().map(VecDeque::len).max().unwrap_or(0);
+        let mut drawing = String::new();
+        for row in (0..height).rev() {
+            let slots: Vec<String> = self
+                .0
+                .iter()
+                .map(|stack| match stack.len().checked_sub(row + 1) {
+                    Some(i) => format!("[{}]", stack[i]),
+                    None => "   ".to_string(),
+                })
+                .collect();
+            drawing.push_str(&slots.join(" "));
+            drawing.push('\n');
+        }
+        let labels: Vec<String> = (1..=self.0.len()).map(|i| format!(" {} ", i)).collect();
+        drawing.push_str(&labels.join(" "));
+        drawing.push('\n');
+        drawing
+    }
+
+    fn apply(&mut self, crate_move: &Move, mover: &dyn CrateMover) -> Result<(), String> {
+        let Move { count, from, to } = *crate_move;
+        for stack in [from, to] {
+            if stack == 0 || stack > self.0.len() {
+                return Err(format!(
+                    "there is no stack {}, only 1 to {}",
+                    stack,
+                    self.0.len()
+                ));
             }
         }
+        if from == to {
+            return Err(format!("can't move crates from stack {} onto itself", from));
+        }
+        if count > self.0[from - 1].len() {
+            return Err(format!(
+                "can't take {} crates from stack {}, which only has {}",
+                count,
+                from,
+                self.0[from - 1].len()
+            ));
+        }
+        let mut source = std::mem::take(&mut self.0[from - 1]);
+        mover.move_crates(&mut source, &mut self.0[to - 1], count);
+        self.0[from - 1] = source;
+        Ok(())
     }
-    for crate_move in lines {
-        let op = &crate_move[5..];
-        let (count_str, rest) = op.split_once(" from ").unwrap();
-        let (from_str, to_str) = rest.split_once(" to ").unwrap();
-        let count: usize;
-        let from: usize;
-        let to: usize;
-        (count, from, to) = (
-            count_str.parse().unwrap(),
-            from_str.parse().unwrap(),
-            to_str.parse().unwrap(),
-        );
-        for _ in 0..count {
-            let deal = stacks[from - 1].pop_front().unwrap();
-            stacks[to - 1].push_front(deal);
+}
+
+#[test]
+fn test_stacks_parse() {
+    let lines = fs::read_lines("./test05.txt")
+        .unwrap()
+        .map_while(Result::ok);
+    let stacks = Stacks::parse(lines);
+    assert_eq!(
+        stacks,
+        Stacks(vec![
+            VecDeque::from(['N', 'Z']),
+            VecDeque::from(['D', 'C', 'M']),
+            VecDeque::from(['P'])
+        ])
+    );
+    assert_eq!(stacks.tops(), "NDP");
+}
+
+#[rstest]
+#[case::test("./test05.txt")]
+#[case::input("./input05.txt")]
+fn test_render(#[case] filename: &str) {
+    let lines: Vec<String> = fs::read_lines(filename)
+        .unwrap()
+        .map_while(Result::ok)
+        .take_while(|line| !line.is_empty())
+        .collect();
+    let stacks = Stacks::parse(lines.clone());
+    assert_eq!(stacks.render(), lines.join("\n") + "\n");
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Move {
+    count: usize,
+    from: usize,
+    to: usize,
+}
+
+impl FromStr for Move {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "expected 'move <n> from <stack> to <stack>' but found '{}'",
+                s
+            )
+        };
+        let op = s.strip_prefix("move ").ok_or_else(invalid)?;
+        let (count_str, rest) = op.split_once(" from ").ok_or_else(invalid)?;
+        let (from_str, to_str) = rest.split_once(" to ").ok_or_else(invalid)?;
+        let number = |n: &str| n.parse::<usize>().map_err(|_| invalid());
+        Ok(Move {
+            count: number(count_str)?,
+            from: number(from_str)?,
+            to: number(to_str)?,
+        })
+    }
+}
+
+#[rstest]
+#[case("move 1 from 2 to 1", Ok(Move { count: 1, from: 2, to: 1 }))]
+#[case("move 12 from 3 to 9", Ok(Move { count: 12, from: 3, to: 9 }))]
+#[case(
+    "move one from 2 to 1",
+    Err("expected 'move <n> from <stack> to <stack>' but found 'move one from 2 to 1'")
+)]
+#[case(
+    "shift 1 from 2 to 1",
+    Err("expected 'move <n> from <stack> to <stack>' but found 'shift 1 from 2 to 1'")
+)]
+fn test_move_from_str(#[case] s: &str, #[case] result: Result<Move, &str>) {
+    assert_eq!(s.parse::<Move>(), result.map_err(str::to_string));
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "move {} from {} to {}", self.count, self.from, self.to)
+    }
+}
+
+/// A crane that can rearrange the stacks.
+trait CrateMover {
+    /// Moves the top `count` crates of `from` onto `to`.
+    fn move_crates(&self, from: &mut VecDeque<char>, to: &mut VecDeque<char>, count: usize);
+}
+
+/// Moves crates one at a time, so they end up in reverse order.
+struct CrateMover9000;
+
+impl CrateMover for CrateMover9000 {
+    fn move_crates(&self, from: &mut VecDeque<char>, to: &mut VecDeque<char>, count: usize) {
+        for deal in from.drain(..count) {
+            to.push_front(deal);
         }
     }
-    stacks.into_iter().map(|s| s[0]).collect()
+}
+
+/// Moves several crates at once, keeping them in the same order.
+struct CrateMover9001;
+
+impl CrateMover for CrateMover9001 {
+    fn move_crates(&self, from: &mut VecDeque<char>, to: &mut VecDeque<char>, count: usize) {
+        for deal in from.drain(..count).rev() {
+            to.push_front(deal);
+        }
+    }
+}
+
+#[rstest]
+#[case::one_at_a_time(&CrateMover9000, "CBAXY", "D")]
+#[case::all_at_once(&CrateMover9001, "ABCXY", "D")]
+fn test_crate_mover(#[case] mover: &dyn CrateMover, #[case] to: &str, #[case] from: &str) {
+    let mut stacks = Stacks(vec![
+        VecDeque::from(['A', 'B', 'C', 'D']),
+        VecDeque::from(['X', 'Y']),
+    ]);
+    let crate_move = Move {
+        count: 3,
+        from: 1,
+        to: 2,
+    };
+    stacks.apply(&crate_move, mover).unwrap();
+    assert_eq!(stacks.0[1], to.chars().collect::<VecDeque<char>>());
+    assert_eq!(stacks.0[0], from.chars().collect::<VecDeque<char>>());
+}
+
+#[rstest]
+#[case(Move { count: 1, from: 4, to: 1 }, "there is no stack 4, only 1 to 3")]
+#[case(Move { count: 1, from: 1, to: 0 }, "there is no stack 0, only 1 to 3")]
+#[case(Move { count: 3, from: 1, to: 2 }, "can't take 3 crates from stack 1, which only has 2")]
+#[case(Move { count: 1, from: 2, to: 2 }, "can't move crates from stack 2 onto itself")]
+fn test_apply_errors(#[case] crate_move: Move, #[case] error: &str) {
+    let lines = fs::read_lines("./test05.txt")
+        .unwrap()
+        .map_while(Result::ok);
+    let mut stacks = Stacks::parse(lines);
+    let before = stacks.clone();
+    assert_eq!(
+        stacks.apply(&crate_move, &CrateMover9000),
+        Err(error.to_string())
+    );
+    assert_eq!(stacks, before);
+}
+
+/// The starting stacks and the moves to make, each paired with the line it's on.
+fn procedure_from_file(filename: &str) -> Result<(Stacks, Vec<(usize, Move)>), String> {
+    let mut lines = fs::read_lines(filename)
+        .map_err(|e| e.to_string())?
+        .map_while(Result::ok)
+        .enumerate();
+    let stacks = Stacks::parse(lines.by_ref().map(|(_, line)| line));
+    let moves = lines
+        .map(|(i, line)| match line.parse() {
+            Ok(crate_move) => Ok((i + 1, crate_move)),
+            Err(e) => Err(format!("line {}: {}", i + 1, e)),
+        })
+        .collect::<Result<_, _>>()?;
+    Ok((stacks, moves))
+}
+
+fn rearrange(filename: &str, mover: &dyn CrateMover) -> Result<Stacks, String> {
+    let (mut stacks, moves) = procedure_from_file(filename)?;
+    for (line, crate_move) in moves {
+        stacks
+            .apply(&crate_move, mover)
+            .map_err(|e| format!("line {}: {}", line, e))?;
+    }
+    Ok(stacks)
+}
+
+#[test]
+fn test_rearrange_errors() {
+    assert_eq!(
+        procedure_from_file("./test05.txt").unwrap().1[3],
+        (
+            9,
+            Move {
+                count: 1,
+                from: 1,
+                to: 2
+            }
+        )
+    );
+    assert_eq!(
+        rearrange("./test05-2.txt", &CrateMover9000).err(),
+        Some("line 7: can't take 5 crates from stack 1, which only has 3".to_string())
+    );
+}
+
+#[rstest]
+#[case::one_at_a_time(
+    &CrateMover9000,
+    "        [Z]\n\
+     \x20       [N]\n\
+     \x20       [D]\n\
+     [C] [M] [P]\n\
+     \x201   2   3 \n"
+)]
+#[case::all_at_once(
+    &CrateMover9001,
+    "        [D]\n\
+     \x20       [N]\n\
+     \x20       [Z]\n\
+     [M] [C] [P]\n\
+     \x201   2   3 \n"
+)]
+fn test_rearrange_render(#[case] mover: &dyn CrateMover, #[case] drawing: &str) {
+    assert_eq!(rearrange("./test05.txt", mover).unwrap().render(), drawing);
+}
+
+fn crate_mover(model: &str) -> &'static dyn CrateMover {
+    match model {
+        "9000" => &CrateMover9000,
+        "9001" => &CrateMover9001,
+        _ => panic!("Unknown CrateMover {}", model),
+    }
+}
+
+/// Shows the rearrangement the way the puzzle does: the starting stacks, then after every `every`
+/// moves the moves made and how the stacks look afterwards, finishing with the final stacks.
+pub fn steps(filename: &str, model: &str, every: usize) -> String {
+    assert!(every > 0, "Can't show the stacks every 0 moves");
+    let (mut stacks, moves) = procedure_from_file(filename).unwrap();
+    let mut result = stacks.render();
+    for chunk in moves.chunks(every) {
+        result.push('\n');
+        for (line, crate_move) in chunk {
+            stacks
+                .apply(crate_move, crate_mover(model))
+                .unwrap_or_else(|e| panic!("line {}: {}", line, e));
+            result.push_str(&format!("{}\n", crate_move));
+        }
+        result.push('\n');
+        result.push_str(&stacks.render());
+    }
+    result
+}
+
+#[test]
+fn test_steps() {
+    let every_move = steps("./test05.txt", "9000", 1);
+    assert_eq!(every_move.split("\n\n").count(), 9);
+    let (stacks, _) = procedure_from_file("./test05.txt").unwrap();
+    assert!(every_move.starts_with(&stacks.render()));
+    assert_eq!(
+        steps("./test05.txt", "9001", 3),
+        "    [D]    \n\
+         [N] [C]    \n\
+         [Z] [M] [P]\n\
+         \x201   2   3 \n\
+         \n\
+         move 1 from 2 to 1\n\
+         move 3 from 1 to 3\n\
+         move 2 from 2 to 1\n\
+         \n\
+         \x20       [D]\n\
+         \x20       [N]\n\
+         [C]     [Z]\n\
+         [M]     [P]\n\
+         \x201   2   3 \n\
+         \n\
+         move 1 from 1 to 2\n\
+         \n\
+         \x20       [D]\n\
+         \x20       [N]\n\
+         \x20       [Z]\n\
+         [M] [C] [P]\n\
+         \x201   2   3 \n"
+    );
+}
+
+#[test]
+#[should_panic(expected = "Can't show the stacks every 0 moves")]
+fn test_steps_every_0() {
+    steps("./test05.txt", "9000", 0);
+}
+
+pub fn part_1(filename: &str) -> String {
+    rearrange(filename, &CrateMover9000).unwrap().tops()
 }
 
 #[rstest]
@@ -49,39 +377,7 @@ fn test_part_1(#[case] filename: &str, #[case] result: &str) {
 }
 
 pub fn part_2(filename: &str) -> String {
-    let mut stacks: Vec<VecDeque<char>> = Vec::new();
-    let mut lines = fs::read_lines(filename).unwrap().filter_map(|s| s.ok());
-    let crate_section = lines.by_ref().take_while(|s| s != "");
-    for layer in crate_section {
-        if layer == String::from("") {
-            break;
-        }
-        for (i, doof) in layer.as_bytes().chunks(4).enumerate() {
-            if stacks.len() <= i {
-                stacks.insert(i, VecDeque::new());
-            }
-            if doof[0] == '[' as u8 {
-                stacks[i].push_back(doof[1] as char);
-            }
-        }
-    }
-    for crate_move in lines {
-        let op = &crate_move[5..];
-        let (count_str, rest) = op.split_once(" from ").unwrap();
-        let (from_str, to_str) = rest.split_once(" to ").unwrap();
-        let count: usize;
-        let from: usize;
-        let to: usize;
-        (count, from, to) = (
-            count_str.parse().unwrap(),
-            from_str.parse().unwrap(),
-            to_str.parse().unwrap(),
-        );
-        let mut deal: VecDeque<_> = stacks[from - 1].drain(..count).collect();
-        deal.append(&mut stacks[to - 1]);
-        stacks[to - 1] = deal;
-    }
-    stacks.into_iter().map(|s| s[0]).collect()
+    rearrange(filename, &CrateMover9001).unwrap().tops()
 }
 
 #[rstest]
@@ -1,93 +1,623 @@
-use std::collections::VecDeque;
-use std::fmt::Debug;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Debug, Display};
 use std::fs::File;
 use std::io::Read;
-use std::ops::{Add, Mul};
-use std::str;
+use std::iter::Peekable;
+use std::str::{self, CharIndices};
 
+use itertools::Itertools;
+#[cfg(feature = "bigint")]
+use num_bigint::BigUint;
 use rstest::rstest;
+use serde_json::{json, Value};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl BinOp {
+    /// `None` on overflow, underflow or division by zero.
+    fn apply<W: WorryLevel>(&self, left: &W, right: &W) -> Option<W> {
+        match self {
+            BinOp::Add => left.checked_add(right),
+            BinOp::Sub => left.checked_sub(right),
+            BinOp::Mul => left.checked_mul(right),
+            BinOp::Div => left.checked_div(right),
+            BinOp::Rem => left.checked_rem(right),
+        }
+    }
+}
+
+/// A number type that worry levels can be tracked in. `u64` is enough for the puzzle itself,
+/// `u128` handles tests whose product doesn't fit in a `u64`, and with the `bigint` feature
+/// `BigUint` never overflows.
+/// Each operation returns `None` rather than overflowing or dividing by zero.
+trait WorryLevel: Clone + Display + From<u64> + PartialEq {
+    fn checked_add(&self, other: &Self) -> Option<Self>;
+    fn checked_sub(&self, other: &Self) -> Option<Self>;
+    fn checked_mul(&self, other: &Self) -> Option<Self>;
+    fn checked_div(&self, other: &Self) -> Option<Self>;
+    fn checked_rem(&self, other: &Self) -> Option<Self>;
+
+    fn is_zero(&self) -> bool {
+        *self == Self::from(0)
+    }
+}
+
+macro_rules! impl_worry_level {
+    ($($t:ty),*) => {$(
+        impl WorryLevel for $t {
+            fn checked_add(&self, other: &Self) -> Option<Self> {
+                <$t>::checked_add(*self, *other)
+            }
+
+            fn checked_sub(&self, other: &Self) -> Option<Self> {
+                <$t>::checked_sub(*self, *other)
+            }
+
+            fn checked_mul(&self, other: &Self) -> Option<Self> {
+                <$t>::checked_mul(*self, *other)
+            }
+
+            fn checked_div(&self, other: &Self) -> Option<Self> {
+                <$t>::checked_div(*self, *other)
+            }
+
+            fn checked_rem(&self, other: &Self) -> Option<Self> {
+                <$t>::checked_rem(*self, *other)
+            }
+        }
+    )*};
+}
+
+impl_worry_level!(u64, u128);
+
+#[cfg(feature = "bigint")]
+impl WorryLevel for BigUint {
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        Some(self + other)
+    }
+
+    fn checked_sub(&self, other: &Self) -> Option<Self> {
+        (self >= other).then(|| self - other)
+    }
+
+    fn checked_mul(&self, other: &Self) -> Option<Self> {
+        Some(self * other)
+    }
+
+    fn checked_div(&self, other: &Self) -> Option<Self> {
+        (!other.is_zero()).then(|| self / other)
+    }
+
+    fn checked_rem(&self, other: &Self) -> Option<Self> {
+        (!other.is_zero()).then(|| self % other)
+    }
+}
+
+/// The right-hand side of a monkey's `Operation: new = ...` line.
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Old,
+    Num(u64),
+    BinOp(BinOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn binop(op: BinOp, left: Expr, right: Expr) -> Expr {
+        Expr::BinOp(op, Box::new(left), Box::new(right))
+    }
+
+    fn eval<W: WorryLevel>(&self, old: &W) -> Option<W> {
+        match self {
+            Expr::Old => Some(old.clone()),
+            Expr::Num(n) => Some(W::from(*n)),
+            Expr::BinOp(op, left, right) => op.apply(&left.eval(old)?, &right.eval(old)?),
+        }
+    }
+
+    /// Whether the expression only uses operations that commute with reduction modulo some
+    /// number, i.e. `eval_mod` agrees with `eval` reduced after the fact. Subtraction is left out
+    /// because `eval` fails when it would go below zero, which residues can't tell.
+    fn is_modular(&self) -> bool {
+        match self {
+            Expr::Old | Expr::Num(_) => true,
+            Expr::BinOp(op, left, right) => {
+                matches!(op, BinOp::Add | BinOp::Mul) && left.is_modular() && right.is_modular()
+            }
+        }
+    }
+
+    fn eval_mod(&self, old: u64, modulus: u64) -> u64 {
+        match self {
+            Expr::Old => old % modulus,
+            Expr::Num(n) => n % modulus,
+            Expr::BinOp(op, left, right) => {
+                let (l, r, m) = (
+                    left.eval_mod(old, modulus) as u128,
+                    right.eval_mod(old, modulus) as u128,
+                    modulus as u128,
+                );
+                (match op {
+                    BinOp::Add => (l + r) % m,
+                    BinOp::Mul => (l * r) % m,
+                    BinOp::Sub | BinOp::Div | BinOp::Rem => {
+                        panic!("{:?} is not compatible with modular arithmetic", op)
+                    }
+                }) as u64
+            }
+        }
+    }
+}
+
+/// Recursive descent parser for expressions over `old`, integer literals, `+ - * / %` and
+/// parentheses, with the usual precedence and left associativity.
+struct ExprParser<'a> {
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl<'a> ExprParser<'a> {
+    fn parse(s: &'a str) -> Result<Expr, String> {
+        let mut parser = ExprParser {
+            chars: s.char_indices().peekable(),
+        };
+        let expr = parser.sum()?;
+        parser.skip_whitespace();
+        match parser.chars.next() {
+            None => Ok(expr),
+            Some((i, c)) => Err(format!("unexpected '{}' at column {}", c, i + 1)),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+    }
+
+    fn peek_op(&mut self, ops: &[(char, BinOp)]) -> Option<BinOp> {
+        self.skip_whitespace();
+        let (_, c) = self.chars.peek()?;
+        let (_, op) = ops.iter().find(|(symbol, _)| symbol == c)?;
+        self.chars.next();
+        Some(*op)
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.product()?;
+        while let Some(op) = self.peek_op(&[('+', BinOp::Add), ('-', BinOp::Sub)]) {
+            expr = Expr::binop(op, expr, self.product()?);
+        }
+        Ok(expr)
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut expr = self.atom()?;
+        while let Some(op) =
+            self.peek_op(&[('*', BinOp::Mul), ('/', BinOp::Div), ('%', BinOp::Rem)])
+        {
+            expr = Expr::binop(op, expr, self.atom()?);
+        }
+        Ok(expr)
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            None => Err("unexpected end of expression".to_string()),
+            Some((_, '(')) => {
+                let expr = self.sum()?;
+                self.skip_whitespace();
+                match self.chars.next() {
+                    Some((_, ')')) => Ok(expr),
+                    Some((i, c)) => Err(format!(
+                        "expected ')' but found '{}' at column {}",
+                        c,
+                        i + 1
+                    )),
+                    None => Err("expected ')' but found end of expression".to_string()),
+                }
+            }
+            Some((_, c)) if c.is_ascii_digit() => {
+                let mut digits = String::from(c);
+                while let Some((_, d)) = self.chars.next_if(|(_, d)| d.is_ascii_digit()) {
+                    digits.push(d);
+                }
+                digits
+                    .parse()
+                    .map(Expr::Num)
+                    .map_err(|e| format!("invalid number {}: {}", digits, e))
+            }
+            Some((i, c)) if c.is_ascii_alphabetic() => {
+                let mut word = String::from(c);
+                while let Some((_, d)) = self.chars.next_if(|(_, d)| d.is_ascii_alphanumeric()) {
+                    word.push(d);
+                }
+                match &word[..] {
+                    "old" => Ok(Expr::Old),
+                    _ => Err(format!("unknown variable '{}' at column {}", word, i + 1)),
+                }
+            }
+            Some((i, c)) => Err(format!("unexpected '{}' at column {}", c, i + 1)),
+        }
+    }
+}
+
+#[rstest]
+#[case("old * 19", Expr::binop(BinOp::Mul, Expr::Old, Expr::Num(19)))]
+#[case("old * old", Expr::binop(BinOp::Mul, Expr::Old, Expr::Old))]
+#[case("3+old", Expr::binop(BinOp::Add, Expr::Num(3), Expr::Old))]
+#[case(
+    "old - 2 - 1",
+    Expr::binop(
+        BinOp::Sub,
+        Expr::binop(BinOp::Sub, Expr::Old, Expr::Num(2)),
+        Expr::Num(1)
+    )
+)]
+#[case(
+    "old + 2 * old",
+    Expr::binop(
+        BinOp::Add,
+        Expr::Old,
+        Expr::binop(BinOp::Mul, Expr::Num(2), Expr::Old)
+    )
+)]
+#[case(
+    "(old + 2) % 7",
+    Expr::binop(
+        BinOp::Rem,
+        Expr::binop(BinOp::Add, Expr::Old, Expr::Num(2)),
+        Expr::Num(7)
+    )
+)]
+fn test_parse_expr(#[case] s: &str, #[case] expected: Expr) {
+    assert_eq!(ExprParser::parse(s), Ok(expected));
+}
+
+#[rstest]
+#[case("old ^ 2", "unexpected '^' at column 5")]
+#[case("old *", "unexpected end of expression")]
+#[case("(old + 1", "expected ')' but found end of expression")]
+#[case("new + 1", "unknown variable 'new' at column 1")]
+#[case("old 2", "unexpected '2' at column 5")]
+fn test_parse_expr_error(#[case] s: &str, #[case] error: &str) {
+    assert_eq!(ExprParser::parse(s), Err(error.to_string()));
+}
+
+#[rstest]
+#[case("old * 19", 79, 1501)]
+#[case("old * old", 79, 6241)]
+#[case("old + 6", 54, 60)]
+#[case("(old + 2) * (old - 2) / 3 % 10", 10, 2)]
+fn test_eval_expr(#[case] s: &str, #[case] old: u64, #[case] result: u64) {
+    assert_eq!(ExprParser::parse(s).unwrap().eval(&old), Some(result));
+}
+
+#[rstest]
+#[case("old * old", u64::MAX / 2, None)]
+#[case("old * old", u64::MAX as u128 / 2, Some(u64::MAX as u128 / 2 * (u64::MAX as u128 / 2)))]
+#[case("old - 7", 6_u64, None)]
+#[case("old / (old - 6)", 6_u64, None)]
+fn test_eval_expr_overflow<W: WorryLevel + Debug + PartialEq>(
+    #[case] s: &str,
+    #[case] old: W,
+    #[case] result: Option<W>,
+) {
+    assert_eq!(ExprParser::parse(s).unwrap().eval(&old), result);
+}
+
+#[rstest]
+#[case("old * old + 3", 1234, true)]
+#[case("(old + 7) * 5", 1234, true)]
+#[case("(old - 7) * 5", 1234, false)]
+#[case("old / 3", 1234, false)]
+#[case("(old + 1) % 4", 1234, false)]
+fn test_eval_mod_expr(#[case] s: &str, #[case] old: u64, #[case] modular: bool) {
+    let expr = ExprParser::parse(s).unwrap();
+    assert_eq!(expr.is_modular(), modular);
+    if modular {
+        for modulus in [2, 13, 23, 9699690] {
+            assert_eq!(
+                Some(expr.eval_mod(old, modulus)),
+                expr.eval(&old).map(|w| w % modulus)
+            );
+        }
+    }
+}
 
 #[derive(Debug, PartialEq)]
 struct Monkey {
     items: VecDeque<u64>,
-    operator: fn(u64, u64) -> u64,
-    operand: Option<u64>,
+    operation: Expr,
     test: u64,
     if_true: usize,
     if_false: usize,
 }
 
-fn monkeys_from_file(filename: &str) -> VecDeque<Monkey> {
+/// Fields collected for one `Monkey N:` block, each checked for presence once the block ends.
+#[derive(Default)]
+struct MonkeyBuilder {
+    id: usize,
+    line_number: usize,
+    items: Option<VecDeque<u64>>,
+    operation: Option<Expr>,
+    test: Option<u64>,
+    if_true: Option<(usize, usize)>,
+    if_false: Option<(usize, usize)>,
+}
+
+impl MonkeyBuilder {
+    fn build(self) -> Result<Monkey, String> {
+        let missing = |label| {
+            format!(
+                "line {}: monkey {} has no '{}' line",
+                self.line_number, self.id, label
+            )
+        };
+        Ok(Monkey {
+            items: self.items.ok_or_else(|| missing("Starting items"))?,
+            operation: self.operation.ok_or_else(|| missing("Operation"))?,
+            test: self.test.ok_or_else(|| missing("Test"))?,
+            if_true: self.if_true.ok_or_else(|| missing("If true"))?.0,
+            if_false: self.if_false.ok_or_else(|| missing("If false"))?.0,
+        })
+    }
+}
+
+fn set_field<T>(field: &mut Option<T>, label: &str, value: T) -> Result<(), String> {
+    match field.replace(value) {
+        Some(_) => Err(format!("duplicate '{}' line", label)),
+        None => Ok(()),
+    }
+}
+
+fn parse_number<T>(s: &str) -> Result<T, String>
+where
+    T: str::FromStr,
+    T::Err: Display,
+{
+    s.trim()
+        .parse()
+        .map_err(|e| format!("invalid number '{}': {}", s.trim(), e))
+}
+
+fn parse_after<T>(value: &str, prefix: &str) -> Result<T, String>
+where
+    T: str::FromStr,
+    T::Err: Display,
+{
+    match value.strip_prefix(prefix) {
+        Some(n) => parse_number(n),
+        None => Err(format!(
+            "expected '{}<number>' but found '{}'",
+            prefix, value
+        )),
+    }
+}
+
+fn monkeys_from_str(s: &str) -> Result<Vec<Monkey>, String> {
+    let mut builders: Vec<MonkeyBuilder> = Vec::new();
+    for (i, line) in s.lines().enumerate() {
+        let line_number = i + 1;
+        let at_line = |e: String| format!("line {}: {}", line_number, e);
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (label, value) = line
+            .split_once(':')
+            .ok_or_else(|| at_line(format!("expected '<label>: <value>' but found '{}'", line)))?;
+        let value = value.trim();
+        if let Some(id) = label.strip_prefix("Monkey ") {
+            builders.push(MonkeyBuilder {
+                id: parse_number(id).map_err(at_line)?,
+                line_number,
+                ..Default::default()
+            });
+            continue;
+        }
+        let builder = builders
+            .last_mut()
+            .ok_or_else(|| at_line(format!("'{}' appears before any 'Monkey N:' line", label)))?;
+        match label {
+            "Starting items" => {
+                let items = value
+                    .split(',')
+                    .filter(|item| !item.trim().is_empty())
+                    .map(parse_number)
+                    .collect::<Result<_, _>>()
+                    .map_err(at_line)?;
+                set_field(&mut builder.items, label, items)
+            }
+            "Operation" => {
+                let expr = value
+                    .strip_prefix("new")
+                    .map(str::trim_start)
+                    .and_then(|rest| rest.strip_prefix('='))
+                    .map(str::trim_start)
+                    .ok_or_else(|| {
+                        at_line(format!(
+                            "expected 'new = <expression>' but found '{}'",
+                            value
+                        ))
+                    })?;
+                let operation = ExprParser::parse(expr)
+                    .map_err(|e| at_line(format!("invalid operation '{}': {}", value, e)))?;
+                set_field(&mut builder.operation, label, operation)
+            }
+            "Test" => {
+                let test = parse_after(value, "divisible by ").map_err(at_line)?;
+                if test == 0 {
+                    return Err(at_line("cannot test divisibility by 0".to_string()));
+                }
+                set_field(&mut builder.test, label, test)
+            }
+            "If true" => {
+                let target = parse_after(value, "throw to monkey ").map_err(at_line)?;
+                set_field(&mut builder.if_true, label, (target, line_number))
+            }
+            "If false" => {
+                let target = parse_after(value, "throw to monkey ").map_err(at_line)?;
+                set_field(&mut builder.if_false, label, (target, line_number))
+            }
+            _ => Err(format!("unknown field '{}'", label)),
+        }
+        .map_err(at_line)?;
+    }
+
+    builders.sort_by_key(|b| b.id);
+    for (index, builder) in builders.iter().enumerate() {
+        if builder.id < index {
+            return Err(format!(
+                "line {}: duplicate monkey {}",
+                builder.line_number, builder.id
+            ));
+        } else if builder.id > index {
+            return Err(format!(
+                "line {}: monkey {} is defined but monkey {} is missing",
+                builder.line_number, builder.id, index
+            ));
+        }
+        for (target, line_number) in [builder.if_true, builder.if_false].into_iter().flatten() {
+            if target >= builders.len() {
+                return Err(format!(
+                    "line {}: monkey {} throws to nonexistent monkey {}",
+                    line_number, builder.id, target
+                ));
+            } else if target == builder.id {
+                return Err(format!(
+                    "line {}: monkey {} throws to itself",
+                    line_number, builder.id
+                ));
+            }
+        }
+    }
+    builders.into_iter().map(MonkeyBuilder::build).collect()
+}
+
+#[rstest]
+#[case::reordered_and_indented(
+    "\n\
+     \tMonkey 1:\n\
+     \t  Starting items: 54, 65\n\
+     \t  Test: divisible by 19\n\
+     \t  Operation: new=old+6\n\
+     \t    If false: throw to monkey 0\n\
+     \t    If true: throw to monkey 0\n\
+     \n\
+     \n\
+     Monkey 0:\r\n\
+     Starting items:\r\n\
+     Operation: new = old * old\r\n\
+     Test: divisible by 23\r\n\
+     If true: throw to monkey 1\r\n\
+     If false: throw to monkey 1\r\n\
+     \n\
+     \n",
+    vec![
+        Monkey {
+            items: VecDeque::new(),
+            operation: Expr::binop(BinOp::Mul, Expr::Old, Expr::Old),
+            test: 23,
+            if_true: 1,
+            if_false: 1,
+        },
+        Monkey {
+            items: VecDeque::from([54, 65]),
+            operation: Expr::binop(BinOp::Add, Expr::Old, Expr::Num(6)),
+            test: 19,
+            if_true: 0,
+            if_false: 0,
+        },
+    ]
+)]
+fn test_monkeys_from_str(#[case] s: &str, #[case] expected: Vec<Monkey>) {
+    assert_eq!(monkeys_from_str(s), Ok(expected));
+}
+
+#[rstest]
+#[case::target_missing(
+    "Monkey 0:\nStarting items: 1\nOperation: new = old\nTest: divisible by 2\nIf true: throw to monkey 1\nIf false: throw to monkey 2",
+    "line 5: monkey 0 throws to nonexistent monkey 1"
+)]
+#[case::self_throw(
+    "Monkey 0:\nStarting items: 1\nOperation: new = old\nTest: divisible by 2\nIf true: throw to monkey 0\nIf false: throw to monkey 0",
+    "line 5: monkey 0 throws to itself"
+)]
+#[case::field_missing(
+    "Monkey 0:\nStarting items: 1\nOperation: new = old\nIf true: throw to monkey 1\nIf false: throw to monkey 1\n\
+     Monkey 1:\nStarting items: 1\nOperation: new = old\nTest: divisible by 2\nIf true: throw to monkey 0\nIf false: throw to monkey 0",
+    "line 1: monkey 0 has no 'Test' line"
+)]
+#[case::duplicate_field(
+    "Monkey 0:\nTest: divisible by 2\nTest: divisible by 3",
+    "line 3: duplicate 'Test' line"
+)]
+#[case::duplicate_monkey("Monkey 0:\nMonkey 0:", "line 2: duplicate monkey 0")]
+#[case::gap(
+    "Monkey 0:\nMonkey 2:",
+    "line 2: monkey 2 is defined but monkey 1 is missing"
+)]
+#[case::unknown_field("Monkey 0:\nMood: grumpy", "line 2: unknown field 'Mood'")]
+#[case::before_header(
+    "Test: divisible by 2",
+    "line 1: 'Test' appears before any 'Monkey N:' line"
+)]
+#[case::bad_test(
+    "Monkey 0:\nTest: divisible by x",
+    "line 2: invalid number 'x': invalid digit found in string"
+)]
+#[case::zero_test(
+    "Monkey 0:\nTest: divisible by 0",
+    "line 2: cannot test divisibility by 0"
+)]
+#[case::bad_target(
+    "Monkey 0:\nIf true: give to monkey 1",
+    "line 2: expected 'throw to monkey <number>' but found 'give to monkey 1'"
+)]
+fn test_monkeys_from_str_error(#[case] s: &str, #[case] error: &str) {
+    assert_eq!(monkeys_from_str(s), Err(error.to_string()));
+}
+
+fn monkeys_from_file(filename: &str) -> Result<Vec<Monkey>, String> {
     let mut buf = Vec::new();
     File::open(filename).unwrap().read_to_end(&mut buf).unwrap();
-    String::from_utf8(buf)
-        .unwrap()
-        .split("\n\n")
-        .into_iter()
-        .map(|monkey_str| {
-            let monkey_lines: Vec<&str> = monkey_str.split("\n").collect();
-            let items = monkey_lines[1][18..]
-                .split(", ")
-                .map(|i| i.parse().unwrap())
-                .collect();
-            let (operator_str, argument_str) = monkey_lines[2][23..].split_once(' ').unwrap();
-            let operator = match operator_str {
-                "*" => u64::mul,
-                "+" => u64::add,
-                _ => panic!("Unknown operator"),
-            };
-            let operand = match argument_str {
-                "old" => None,
-                s => Some(s.parse().unwrap()),
-            };
-            let test = monkey_lines[3][21..].parse().unwrap();
-            let if_true = monkey_lines[4][29..].parse().unwrap();
-            let if_false = monkey_lines[5][30..].parse().unwrap();
-            Monkey {
-                items,
-                operator,
-                operand,
-                test,
-                if_true,
-                if_false,
-            }
-        })
-        .collect()
+    monkeys_from_str(&String::from_utf8(buf).unwrap())
 }
 
 #[test]
 fn test_monkeys_from_file() {
     assert_eq!(
         monkeys_from_file("./test11.txt"),
-        VecDeque::from([
+        Ok(vec![
             Monkey {
                 items: VecDeque::from([79, 98]),
-                operator: u64::mul,
-                operand: Some(19),
+                operation: Expr::binop(BinOp::Mul, Expr::Old, Expr::Num(19)),
                 test: 23,
                 if_true: 2,
                 if_false: 3
             },
             Monkey {
                 items: VecDeque::from([54, 65, 75, 74]),
-                operator: u64::add,
-                operand: Some(6),
+                operation: Expr::binop(BinOp::Add, Expr::Old, Expr::Num(6)),
                 test: 19,
                 if_true: 2,
                 if_false: 0
             },
             Monkey {
                 items: VecDeque::from([79, 60, 97]),
-                operator: u64::mul,
-                operand: None,
+                operation: Expr::binop(BinOp::Mul, Expr::Old, Expr::Old),
                 test: 13,
                 if_true: 1,
                 if_false: 3
             },
             Monkey {
                 items: VecDeque::from([74]),
-                operator: u64::add,
-                operand: Some(3),
+                operation: Expr::binop(BinOp::Add, Expr::Old, Expr::Num(3)),
                 test: 17,
                 if_true: 0,
                 if_false: 1
@@ -96,50 +626,369 @@ fn test_monkeys_from_file() {
     );
 }
 
-fn do_monkey_business(mut monkeys: VecDeque<Monkey>, rounds: usize, relief: u64) -> u64 {
-    let num_monkeys = monkeys.len();
-    let mut inspections = vec![0; num_monkeys];
+#[test]
+fn test_monkeys_from_file_bad_operation() {
+    assert_eq!(
+        monkeys_from_file("./test11-2.txt"),
+        Err("line 10: invalid operation 'new = old ^ 2': unexpected '^' at column 5".to_string())
+    );
+}
+
+/// Hooks called by `do_monkey_business` as the simulation progresses. Rounds are numbered from 1.
+trait MonkeyObserver<W> {
+    fn item_thrown(&mut self, _round: usize, _from: usize, _to: usize, _worry: &W) {}
+
+    fn round_finished(&mut self, _round: usize, _holdings: &[VecDeque<W>], _inspections: &[u64]) {}
+}
+
+impl<W> MonkeyObserver<W> for () {}
+
+#[derive(Debug, PartialEq)]
+struct RoundSnapshot {
+    round: usize,
+    holdings: Vec<Vec<u64>>,
+    inspections: Vec<u64>,
+}
+
+/// Records which items each monkey holds, and how many it has inspected so far, after every round.
+#[derive(Default)]
+struct RoundRecorder {
+    rounds: Vec<RoundSnapshot>,
+}
+
+impl MonkeyObserver<u64> for RoundRecorder {
+    fn round_finished(&mut self, round: usize, holdings: &[VecDeque<u64>], inspections: &[u64]) {
+        self.rounds.push(RoundSnapshot {
+            round,
+            holdings: holdings
+                .iter()
+                .map(|items| items.iter().copied().collect())
+                .collect(),
+            inspections: inspections.to_vec(),
+        });
+    }
+}
+
+impl RoundRecorder {
+    fn to_csv(&self) -> String {
+        let mut csv = String::from("round,monkey,inspections,items\n");
+        for snapshot in &self.rounds {
+            for (monkey, (items, inspections)) in snapshot
+                .holdings
+                .iter()
+                .zip(&snapshot.inspections)
+                .enumerate()
+            {
+                csv.push_str(&format!(
+                    "{},{},{},{}\n",
+                    snapshot.round,
+                    monkey,
+                    inspections,
+                    items.iter().map(u64::to_string).join(" ")
+                ));
+            }
+        }
+        csv
+    }
+
+    fn to_json(&self) -> String {
+        Value::Array(
+            self.rounds
+                .iter()
+                .map(|snapshot| {
+                    json!({
+                        "round": snapshot.round,
+                        "items": snapshot.holdings,
+                        "inspections": snapshot.inspections,
+                    })
+                })
+                .collect(),
+        )
+        .to_string()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Throw {
+    round: usize,
+    from: usize,
+    to: usize,
+    worry: u64,
+}
+
+/// Records every item thrown, along with its worry level as it leaves the thrower.
+#[derive(Default)]
+struct ThrowRecorder {
+    throws: Vec<Throw>,
+}
+
+impl MonkeyObserver<u64> for ThrowRecorder {
+    fn item_thrown(&mut self, round: usize, from: usize, to: usize, worry: &u64) {
+        self.throws.push(Throw {
+            round,
+            from,
+            to,
+            worry: *worry,
+        });
+    }
+}
+
+impl ThrowRecorder {
+    fn to_csv(&self) -> String {
+        let mut csv = String::from("round,from,to,worry\n");
+        for throw in &self.throws {
+            csv.push_str(&format!(
+                "{},{},{},{}\n",
+                throw.round, throw.from, throw.to, throw.worry
+            ));
+        }
+        csv
+    }
+
+    fn to_json(&self) -> String {
+        Value::Array(
+            self.throws
+                .iter()
+                .map(|throw| {
+                    json!({
+                        "round": throw.round,
+                        "from": throw.from,
+                        "to": throw.to,
+                        "worry": throw.worry,
+                    })
+                })
+                .collect(),
+        )
+        .to_string()
+    }
+}
+
+/// Runs the simulation with worry levels held as `W`, reducing them modulo the product of every
+/// monkey's `test` after each inspection. Thrown items are collected in an outbox and delivered
+/// once the throwing monkey has finished its turn. Fails if a worry level, or the product of the
+/// tests itself, doesn't fit in `W`.
+fn do_monkey_business<W, O>(
+    monkeys: &[Monkey],
+    rounds: usize,
+    relief: u64,
+    observer: &mut O,
+) -> Result<u64, String>
+where
+    W: WorryLevel,
+    O: MonkeyObserver<W>,
+{
+    let mut holdings: Vec<VecDeque<W>> = monkeys
+        .iter()
+        .map(|m| m.items.iter().map(|&item| W::from(item)).collect())
+        .collect();
+    let mut inspections = vec![0; monkeys.len()];
+    let mut outbox: Vec<(usize, W)> = Vec::new();
 
-    let max_worry = monkeys.iter().map(|m| m.test).fold(1, u64::mul);
-    println!("{}", max_worry);
+    let max_worry = monkeys
+        .iter()
+        .try_fold(W::from(1), |acc, m| acc.checked_mul(&W::from(m.test)))
+        .ok_or("the product of the monkeys' tests overflowed")?;
+    let relief = W::from(relief);
 
-    for _ in 0..rounds {
-        for i in 0..num_monkeys {
-            let mut monkey = monkeys.pop_front().unwrap();
-            for _ in 0..monkey.items.len() {
-                let mut item = monkey.items.pop_front().unwrap();
+    for round in 1..=rounds {
+        for (i, monkey) in monkeys.iter().enumerate() {
+            let test = W::from(monkey.test);
+            for item in holdings[i].drain(..) {
                 inspections[i] += 1;
-                item = ((monkey.operator)(
-                    item,
-                    match monkey.operand {
-                        Some(n) => n,
-                        None => item,
-                    },
-                ) / relief)
-                    % max_worry;
-                if item % monkey.test == 0 {
-                    let idx = match i < monkey.if_true {
-                        true => monkey.if_true - 1 - i,
-                        false => monkey.if_true + num_monkeys - 1 - i,
-                    };
-                    monkeys[idx].items.push_back(item);
-                } else {
-                    let idx = match i < monkey.if_false {
-                        true => monkey.if_false - 1 - i,
-                        false => monkey.if_false + num_monkeys - 1 - i,
-                    };
-                    monkeys[idx].items.push_back(item);
-                }
+                let item = monkey
+                    .operation
+                    .eval(&item)
+                    .and_then(|w| w.checked_div(&relief))
+                    .and_then(|w| w.checked_rem(&max_worry))
+                    .ok_or_else(|| {
+                        format!(
+                            "round {}: worry level of {} overflowed at monkey {}",
+                            round, item, i
+                        )
+                    })?;
+                let target = match item.checked_rem(&test).unwrap().is_zero() {
+                    true => monkey.if_true,
+                    false => monkey.if_false,
+                };
+                observer.item_thrown(round, i, target, &item);
+                outbox.push((target, item));
+            }
+            for (target, item) in outbox.drain(..) {
+                holdings[target].push_back(item);
             }
-            monkeys.push_back(monkey);
         }
+        observer.round_finished(round, &holdings, &inspections);
     }
     inspections.sort();
-    inspections.into_iter().rev().take(2).fold(1, u64::mul)
+    Ok(inspections.into_iter().rev().take(2).product())
+}
+
+#[rstest]
+#[case::test("./test11.txt", 2713310158)]
+#[case::input("./input11.txt", 13606755504)]
+fn test_do_monkey_business_u128(#[case] filename: &str, #[case] result: u64) {
+    let monkeys = monkeys_from_file(filename).unwrap();
+    assert_eq!(
+        do_monkey_business::<u128, _>(&monkeys, 10_000, 1, &mut ()),
+        Ok(result)
+    );
+}
+
+#[cfg(feature = "bigint")]
+#[rstest]
+#[case::test("./test11.txt", 2713310158)]
+#[case::input("./input11.txt", 13606755504)]
+fn test_do_monkey_business_bigint(#[case] filename: &str, #[case] result: u64) {
+    let monkeys = monkeys_from_file(filename).unwrap();
+    assert_eq!(
+        do_monkey_business::<BigUint, _>(&monkeys, 10_000, 1, &mut ()),
+        Ok(result)
+    );
+}
+
+/// Seven monkeys in a ring whose tests multiply to more than `u64::MAX`.
+#[cfg(test)]
+fn wide_monkeys() -> Vec<Monkey> {
+    let tests = [1009, 1013, 1019, 1021, 1031, 1033, 1039];
+    let input: String = tests
+        .iter()
+        .enumerate()
+        .map(|(i, test)| {
+            format!(
+                "Monkey {}:\n  Starting items: {}, {}\n  Operation: new = old * {}\n  \
+                 Test: divisible by {}\n    If true: throw to monkey {}\n    \
+                 If false: throw to monkey {}\n\n",
+                i,
+                i + 2,
+                i * 7 + 5,
+                i + 2,
+                test,
+                (i + 3) % tests.len(),
+                (i + 1) % tests.len()
+            )
+        })
+        .collect();
+    monkeys_from_str(&input).unwrap()
+}
+
+#[test]
+fn test_do_monkey_business_wide_tests() {
+    let monkeys = wide_monkeys();
+    assert_eq!(
+        do_monkey_business::<u64, _>(&monkeys, 1_000, 1, &mut ()),
+        Err("the product of the monkeys' tests overflowed".to_string())
+    );
+    assert_eq!(
+        do_monkey_business::<u128, _>(&monkeys, 1_000, 1, &mut ()),
+        Ok(195972000)
+    );
+}
+
+#[cfg(feature = "bigint")]
+#[test]
+fn test_do_monkey_business_wide_tests_bigint() {
+    assert_eq!(
+        do_monkey_business::<BigUint, _>(&wide_monkeys(), 1_000, 1, &mut ()),
+        Ok(195972000)
+    );
+}
+
+#[test]
+fn test_round_recorder() {
+    let mut recorder = RoundRecorder::default();
+    do_monkey_business(
+        &monkeys_from_file("./test11.txt").unwrap(),
+        20,
+        3,
+        &mut recorder,
+    )
+    .unwrap();
+    assert_eq!(
+        recorder.rounds[0],
+        RoundSnapshot {
+            round: 1,
+            holdings: vec![
+                vec![20, 23, 27, 26],
+                vec![2080, 25, 167, 207, 401, 1046],
+                vec![],
+                vec![]
+            ],
+            inspections: vec![2, 4, 3, 5],
+        }
+    );
+    assert_eq!(recorder.rounds[19].inspections, vec![101, 95, 7, 105]);
+    assert_eq!(
+        recorder.to_csv().lines().take(3).collect::<Vec<_>>(),
+        vec![
+            "round,monkey,inspections,items",
+            "1,0,2,20 23 27 26",
+            "1,1,4,2080 25 167 207 401 1046",
+        ]
+    );
+}
+
+#[test]
+fn test_throw_recorder() {
+    let mut recorder = ThrowRecorder::default();
+    do_monkey_business(
+        &monkeys_from_file("./test11.txt").unwrap(),
+        1,
+        3,
+        &mut recorder,
+    )
+    .unwrap();
+    assert_eq!(recorder.throws.len(), 14);
+    assert_eq!(
+        recorder.throws[..2],
+        [
+            Throw {
+                round: 1,
+                from: 0,
+                to: 3,
+                worry: 500
+            },
+            Throw {
+                round: 1,
+                from: 0,
+                to: 3,
+                worry: 620
+            },
+        ]
+    );
+    assert_eq!(
+        recorder.to_json()[..80],
+        *r#"[{"from":0,"round":1,"to":3,"worry":500},{"from":0,"round":1,"to":3,"worry":620}"#
+    );
+}
+
+/// Runs the part 2 simulation and dumps either `rounds` or `throws` as `csv` or `json`.
+pub fn trace(filename: &str, table: &str, format: &str) -> String {
+    let monkeys = monkeys_from_file(filename).unwrap();
+    match table {
+        "rounds" => {
+            let mut recorder = RoundRecorder::default();
+            do_monkey_business(&monkeys, 10_000, 1, &mut recorder).unwrap();
+            match format {
+                "csv" => recorder.to_csv(),
+                "json" => recorder.to_json(),
+                _ => panic!("Unknown trace format {}", format),
+            }
+        }
+        "throws" => {
+            let mut recorder = ThrowRecorder::default();
+            do_monkey_business(&monkeys, 10_000, 1, &mut recorder).unwrap();
+            match format {
+                "csv" => recorder.to_csv(),
+                "json" => recorder.to_json(),
+                _ => panic!("Unknown trace format {}", format),
+            }
+        }
+        _ => panic!("Unknown trace table {}", table),
+    }
 }
 
 pub fn part_1(filename: &str) -> u64 {
-    do_monkey_business(monkeys_from_file(filename), 20, 3)
+    do_monkey_business::<u64, _>(&monkeys_from_file(filename).unwrap(), 20, 3, &mut ()).unwrap()
 }
 
 #[rstest]
@@ -150,7 +999,7 @@ fn test_part_1(#[case] filename: &str, #[case] result: u64) {
 }
 
 pub fn part_2(filename: &str) -> u64 {
-    do_monkey_business(monkeys_from_file(filename), 10_000, 1)
+    do_monkey_business::<u64, _>(&monkeys_from_file(filename).unwrap(), 10_000, 1, &mut ()).unwrap()
 }
 
 #[rstest]
@@ -159,3 +1008,188 @@ pub fn part_2(filename: &str) -> u64 {
 fn test_part_2(#[case] filename: &str, #[case] result: u64) {
     assert_eq!(part_2(filename), result);
 }
+
+/// An item's worry level, either exactly or as its residue modulo each monkey's `test`.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+enum Worry {
+    Exact(u64),
+    Residues(Vec<u64>),
+}
+
+/// Follows items one at a time rather than round by round. Items never affect each other, and
+/// an item only moves on to a later monkey within the same round, so the `(monkey, worry)` state
+/// at the start of a round fully determines what happens to it from then on.
+struct ItemTracker<'a> {
+    monkeys: &'a [Monkey],
+    relief: u64,
+}
+
+impl ItemTracker<'_> {
+    /// Residues are only usable when there's no relief division and every operation is modular;
+    /// otherwise worry levels are tracked exactly and overflow is reported as an error.
+    fn initial_worry(&self, worry: u64) -> Worry {
+        if self.relief == 1 && self.monkeys.iter().all(|m| m.operation.is_modular()) {
+            Worry::Residues(self.monkeys.iter().map(|m| worry % m.test).collect())
+        } else {
+            Worry::Exact(worry)
+        }
+    }
+
+    /// Plays one round for an item starting at `monkey`, tallying inspections, and returns the
+    /// monkey it ends the round with.
+    fn play_round(
+        &self,
+        mut monkey: usize,
+        worry: &mut Worry,
+        inspections: &mut [u64],
+    ) -> Result<usize, String> {
+        loop {
+            let m = &self.monkeys[monkey];
+            inspections[monkey] += 1;
+            let divisible = match worry {
+                Worry::Exact(w) => {
+                    *w = m
+                        .operation
+                        .eval(w)
+                        .and_then(|w| w.checked_div(self.relief))
+                        .ok_or_else(|| format!("worry overflowed at monkey {}", monkey))?;
+                    w.is_multiple_of(m.test)
+                }
+                Worry::Residues(residues) => {
+                    for (residue, other) in residues.iter_mut().zip(self.monkeys) {
+                        *residue = m.operation.eval_mod(*residue, other.test);
+                    }
+                    residues[monkey] == 0
+                }
+            };
+            let target = if divisible { m.if_true } else { m.if_false };
+            if target < monkey {
+                return Ok(target);
+            }
+            monkey = target;
+        }
+    }
+
+    /// Inspections per monkey caused by one item over `rounds` rounds. Once the item's state at
+    /// the start of a round repeats, the remaining rounds are extrapolated from the cycle.
+    fn item_inspections(
+        &self,
+        mut monkey: usize,
+        mut worry: Worry,
+        rounds: u64,
+    ) -> Result<Vec<u64>, String> {
+        let mut seen: HashMap<(usize, Worry), usize> = HashMap::new();
+        // Cumulative inspections per monkey at the start of each round
+        let mut history = vec![vec![0; self.monkeys.len()]];
+        for round in 0.. {
+            if round as u64 == rounds {
+                break;
+            }
+            if let Some(cycle_start) = seen.insert((monkey, worry.clone()), round) {
+                let cycle_len = (round - cycle_start) as u64;
+                let remaining = rounds - round as u64;
+                let partial = cycle_start + (remaining % cycle_len) as usize;
+                let (start, now) = (&history[cycle_start], &history[round]);
+                return Ok((0..self.monkeys.len())
+                    .map(|i| {
+                        now[i]
+                            + (remaining / cycle_len) * (now[i] - start[i])
+                            + (history[partial][i] - start[i])
+                    })
+                    .collect());
+            }
+            let mut inspections = history[round].clone();
+            monkey = self
+                .play_round(monkey, &mut worry, &mut inspections)
+                .map_err(|e| format!("round {}: {}", round + 1, e))?;
+            history.push(inspections);
+        }
+        Ok(history.pop().unwrap())
+    }
+
+    fn inspections(&self, rounds: u64) -> Result<Vec<u64>, String> {
+        let mut total = vec![0; self.monkeys.len()];
+        for (monkey, m) in self.monkeys.iter().enumerate() {
+            for &item in &m.items {
+                let counts = self.item_inspections(monkey, self.initial_worry(item), rounds)?;
+                for (t, c) in total.iter_mut().zip(counts) {
+                    *t += c;
+                }
+            }
+        }
+        Ok(total)
+    }
+}
+
+#[rstest]
+#[case::part_1("./test11.txt", 20, 3, vec![101, 95, 7, 105])]
+#[case::part_2("./test11.txt", 10_000, 1, vec![52166, 47830, 1938, 52013])]
+fn test_item_tracker(
+    #[case] filename: &str,
+    #[case] rounds: u64,
+    #[case] relief: u64,
+    #[case] result: Vec<u64>,
+) {
+    let monkeys = monkeys_from_file(filename).unwrap();
+    let tracker = ItemTracker {
+        monkeys: &monkeys,
+        relief,
+    };
+    assert_eq!(tracker.inspections(rounds), Ok(result));
+}
+
+#[test]
+fn test_item_tracker_overflow() {
+    let monkeys = monkeys_from_file("./test11.txt").unwrap();
+    let tracker = ItemTracker {
+        monkeys: &monkeys,
+        relief: 2,
+    };
+    assert_eq!(
+        tracker.inspections(10_000),
+        Err("round 23: worry overflowed at monkey 2".to_string())
+    );
+}
+
+/// The monkey business after any number of rounds, found by following items individually.
+pub fn monkey_business_after(filename: &str, rounds: u64, relief: u64) -> Result<u128, String> {
+    let monkeys = monkeys_from_file(filename)?;
+    let tracker = ItemTracker {
+        monkeys: &monkeys,
+        relief,
+    };
+    let mut inspections = tracker.inspections(rounds)?;
+    inspections.sort();
+    Ok(inspections
+        .into_iter()
+        .rev()
+        .take(2)
+        .map(u128::from)
+        .product())
+}
+
+#[rstest]
+#[case::test_part_1("./test11.txt", 20, 3, 10605)]
+#[case::input_part_1("./input11.txt", 20, 3, 54752)]
+#[case::test_part_2("./test11.txt", 10_000, 1, 2713310158)]
+#[case::input_part_2("./input11.txt", 10_000, 1, 13606755504)]
+fn test_monkey_business_after(
+    #[case] filename: &str,
+    #[case] rounds: u64,
+    #[case] relief: u64,
+    #[case] result: u128,
+) {
+    assert_eq!(monkey_business_after(filename, rounds, relief), Ok(result));
+}
+
+#[test]
+fn test_monkey_business_after_overflow() {
+    assert_eq!(
+        monkey_business_after("./test11.txt", 10_000, 2),
+        Err("round 23: worry overflowed at monkey 2".to_string())
+    );
+    assert_eq!(
+        monkey_business_after("./test11.txt", 20, 0),
+        Err("round 1: worry overflowed at monkey 0".to_string())
+    );
+}
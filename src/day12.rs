@@ -2,6 +2,8 @@ use petgraph::algo::dijkstra;
 use petgraph::prelude::GraphMap;
 use rstest::rstest;
 
+use crate::bench;
+use crate::grid_search::{Coord, GridSearch};
 use crate::vec2d;
 use crate::vec2d::Vec2d;
 
@@ -13,10 +15,9 @@ struct Heightmap {
 }
 
 fn map_from_file(filename: &str) -> Vec2d<u8> {
-    let map_vec: Vec<u8>;
     let input = vec2d::input_from_file(filename);
-    let (width, height) = (input.get(0).unwrap().len(), input.len());
-    map_vec = input.into_iter().flatten().collect();
+    let (width, height) = (input.first().unwrap().len(), input.len());
+    let map_vec = input.into_iter().flatten().collect();
     Vec2d::new(map_vec, height, width)
 }
 
@@ -27,12 +28,12 @@ fn heightmap_from_file(filename: &str) -> Heightmap {
     for i in 0..elevations.row_count {
         for j in 0..elevations.col_count {
             let spot = elevations.index_mut(i, j);
-            if *spot == 'S' as u8 {
+            if *spot == b'S' {
                 start = (i, j);
-                *spot = 'a' as u8;
-            } else if *spot == 'E' as u8 {
+                *spot = b'a';
+            } else if *spot == b'E' {
                 end = (i, j);
-                *spot = 'z' as u8;
+                *spot = b'z';
             }
         }
     }
@@ -57,11 +58,60 @@ fn test_heightmap_from_file() {
     assert_eq!(heightmap_from_file("./test12.txt"), result);
 }
 
+/// How far a climber may go up or down in one step, whether they can step diagonally, and what a
+/// step costs given the change in elevation (positive going up).
+struct ClimbRules {
+    max_ascent: u8,
+    max_descent: u8,
+    diagonal: bool,
+    cost: Box<dyn Fn(i32) -> usize>,
+}
+
+impl Default for ClimbRules {
+    /// The puzzle's rules: at most one step up, any distance down, every step costing 1.
+    fn default() -> Self {
+        ClimbRules {
+            max_ascent: 1,
+            max_descent: u8::MAX,
+            diagonal: false,
+            cost: Box::new(|_| 1),
+        }
+    }
+}
+
+impl ClimbRules {
+    fn allows(&self, from: u8, to: u8) -> bool {
+        to <= from.saturating_add(self.max_ascent) && to >= from.saturating_sub(self.max_descent)
+    }
+
+    fn step_cost(&self, from: u8, to: u8) -> usize {
+        (self.cost)(to as i32 - from as i32)
+    }
+}
+
+#[rstest]
+#[case(ClimbRules::default(), b'a', b'b', true)]
+#[case(ClimbRules::default(), b'a', b'c', false)]
+#[case(ClimbRules::default(), b'z', b'a', true)]
+#[case(ClimbRules { max_ascent: 2, ..Default::default() }, b'a', b'c', true)]
+#[case(ClimbRules { max_descent: 1, ..Default::default() }, b'c', b'a', false)]
+#[case(ClimbRules { max_descent: 1, ..Default::default() }, b'c', b'b', true)]
+fn test_climb_rules_allows(
+    #[case] rules: ClimbRules,
+    #[case] from: u8,
+    #[case] to: u8,
+    #[case] result: bool,
+) {
+    assert_eq!(rules.allows(from, to), result);
+}
+
 fn paths_from_heightmap(
     heightmap: &Heightmap,
     reversed_edges: bool,
-) -> GraphMap<(usize, usize), i32, petgraph::Directed> {
+) -> GraphMap<Coord, i32, petgraph::Directed> {
     let mut paths = GraphMap::new();
+    let rules = ClimbRules::default();
+    let search = heightmap.search(&rules);
     for i in 0..heightmap.elevations.row_count {
         for j in 0..heightmap.elevations.col_count {
             paths.add_node((i, j));
@@ -69,54 +119,11 @@ fn paths_from_heightmap(
     }
     for i in 0..heightmap.elevations.row_count {
         for j in 0..heightmap.elevations.col_count {
-            let me = heightmap.elevations.index(i, j);
-            let me_coords = (i, j);
-            if i > 0 {
-                // Add a directed edge, if appropriate, to the square above this one
-                let them = heightmap.elevations.index(i - 1, j);
-                let them_coords = (i - 1, j);
-                if *them <= me + 1 {
-                    if reversed_edges {
-                        paths.add_edge(them_coords, me_coords, 1);
-                    } else {
-                        paths.add_edge(me_coords, them_coords, 1);
-                    }
-                }
-            }
-            if i < heightmap.elevations.row_count - 1 {
-                // The square below
-                let them = heightmap.elevations.index(i + 1, j);
-                let them_coords = (i + 1, j);
-                if *them <= me + 1 {
-                    if reversed_edges {
-                        paths.add_edge(them_coords, me_coords, 1);
-                    } else {
-                        paths.add_edge(me_coords, them_coords, 1);
-                    }
-                }
-            }
-            if j > 0 {
-                // The square to the left
-                let them = heightmap.elevations.index(i, j - 1);
-                let them_coords = (i, j - 1);
-                if *them <= me + 1 {
-                    if reversed_edges {
-                        paths.add_edge(them_coords, me_coords, 1);
-                    } else {
-                        paths.add_edge(me_coords, them_coords, 1);
-                    }
-                }
-            }
-            if j < heightmap.elevations.col_count - 1 {
-                // The square to the right
-                let them = heightmap.elevations.index(i, j + 1);
-                let them_coords = (i, j + 1);
-                if *them <= me + 1 {
-                    if reversed_edges {
-                        paths.add_edge(them_coords, me_coords, 1);
-                    } else {
-                        paths.add_edge(me_coords, them_coords, 1);
-                    }
+            for them in search.neighbours((i, j)) {
+                if reversed_edges {
+                    paths.add_edge(them, (i, j), 1);
+                } else {
+                    paths.add_edge((i, j), them, 1);
                 }
             }
         }
@@ -124,29 +131,169 @@ fn paths_from_heightmap(
     paths
 }
 
-pub fn part_1(filename: &str) -> i32 {
-    let map = heightmap_from_file(filename);
-    let paths = paths_from_heightmap(&map, false);
-    *dijkstra(&paths, map.start, Some(map.end), |_| 1)
-        .get(&map.end)
-        .unwrap()
+impl Heightmap {
+    fn elevation(&self, (i, j): Coord) -> u8 {
+        *self.elevations.index(i, j)
+    }
+
+    fn search<'a>(
+        &'a self,
+        rules: &'a ClimbRules,
+    ) -> GridSearch<'a, u8, impl Fn(Coord, Coord) -> bool + 'a> {
+        let search = GridSearch::new(&self.elevations, |from, to| {
+            rules.allows(self.elevation(from), self.elevation(to))
+        });
+        match rules.diagonal {
+            true => search.with_diagonals(),
+            false => search,
+        }
+    }
+
+    /// The squares visited on a shortest climb from `start` to `end`, including both.
+    fn shortest_path(&self) -> Option<Vec<Coord>> {
+        self.search(&ClimbRules::default())
+            .astar(self.start, self.end)
+    }
+
+    /// The squares visited on a shortest climb to `end` from any square at elevation `a`.
+    fn shortest_path_from_lowest(&self) -> Option<Vec<Coord>> {
+        let starts = (0..self.elevations.row_count)
+            .flat_map(|i| (0..self.elevations.col_count).map(move |j| (i, j)))
+            .filter(|&c| self.elevation(c) == b'a');
+        self.search(&ClimbRules::default())
+            .bfs(starts, |c| c == self.end)
+    }
+
+    /// The cheapest route from `start` to `end` under `rules`, and its total cost.
+    fn cheapest_route(&self, rules: &ClimbRules) -> Option<(usize, Vec<Coord>)> {
+        self.search(rules)
+            .cheapest_path(self.start, self.end, |from, to| {
+                rules.step_cost(self.elevation(from), self.elevation(to))
+            })
+    }
+
+    /// Draws `path` over the map the way the puzzle does: each square on the path shows an arrow
+    /// towards the next one, the end is marked `E` and everything else is `.`. Diagonal steps are
+    /// drawn as `/` or `\\`.
+    fn render_path(&self, path: &[Coord]) -> String {
+        let (rows, cols) = (self.elevations.row_count, self.elevations.col_count);
+        let mut canvas = Vec2d::new(vec![b'.'; rows * cols], rows, cols);
+        for step in path.windows(2) {
+            let ((i, j), (next_i, next_j)) = (step[0], step[1]);
+            *canvas.index_mut(i, j) =
+                match (next_i as isize - i as isize, next_j as isize - j as isize) {
+                    (-1, 0) => b'^',
+                    (1, 0) => b'v',
+                    (0, -1) => b'<',
+                    (0, 1) => b'>',
+                    (-1, 1) | (1, -1) => b'/',
+                    (-1, -1) | (1, 1) => b'\\',
+                    _ => panic!("Path jumps from {:?} to {:?}", (i, j), (next_i, next_j)),
+                };
+        }
+        *canvas.index_mut(self.end.0, self.end.1) = b'E';
+        (0..canvas.row_count)
+            .map(|i| String::from_utf8_lossy(canvas.row(i)) + "\n")
+            .collect()
+    }
+}
+
+#[test]
+fn test_shortest_path() {
+    let map = heightmap_from_file("./test12.txt");
+    let path = map.shortest_path().unwrap();
+    assert_eq!(path.len(), 32);
+    assert_eq!(path.first(), Some(&map.start));
+    assert_eq!(path.last(), Some(&map.end));
+    for step in path.windows(2) {
+        let ((i, j), (next_i, next_j)) = (step[0], step[1]);
+        assert_eq!(i.abs_diff(next_i) + j.abs_diff(next_j), 1);
+        assert!(*map.elevations.index(next_i, next_j) <= map.elevations.index(i, j) + 1);
+    }
 }
 
 #[rstest]
-#[case::test("./test12.txt", 31)]
-#[case::input("./input12.txt", 497)]
-fn test_part_1(#[case] filename: &str, #[case] result: i32) {
-    assert_eq!(part_1(filename), result);
+#[case::puzzle(ClimbRules::default(), 31, 31)]
+#[case::double_climbing_cost(
+    ClimbRules {
+        cost: Box::new(|change| if change > 0 { 2 } else { 1 }),
+        ..Default::default()
+    },
+    31,
+    56
+)]
+#[case::free_descent(
+    ClimbRules {
+        cost: Box::new(|change| if change < 0 { 0 } else { 1 }),
+        ..Default::default()
+    },
+    31,
+    31
+)]
+#[case::diagonal(ClimbRules { diagonal: true, ..Default::default() }, 27, 27)]
+#[case::gentle_descent(ClimbRules { max_descent: 1, ..Default::default() }, 31, 31)]
+#[case::big_steps(ClimbRules { max_ascent: 25, ..Default::default() }, 7, 7)]
+fn test_cheapest_route(#[case] rules: ClimbRules, #[case] steps: usize, #[case] cost: usize) {
+    let map = heightmap_from_file("./test12.txt");
+    let (route_cost, path) = map.cheapest_route(&rules).unwrap();
+    assert_eq!((path.len() - 1, route_cost), (steps, cost));
 }
 
-pub fn part_2(filename: &str) -> i32 {
+#[test]
+fn test_render_path() {
+    let map = heightmap_from_file("./test12.txt");
+    let path = map.shortest_path().unwrap();
+    assert_eq!(
+        map.render_path(&path),
+        ">>vv<<<<\n\
+         ..vvv<<^\n\
+         ..vv>E^^\n\
+         ..v>>>^^\n\
+         ..>>>>>^\n"
+    );
+}
+
+pub fn path(filename: &str) -> String {
     let map = heightmap_from_file(filename);
-    let paths = paths_from_heightmap(&map, true);
+    map.render_path(&map.shortest_path().unwrap())
+}
+
+/// The cheapest route under custom rules, where each step up costs `ascent_cost` and any other
+/// step costs 1.
+pub fn route(
+    filename: &str,
+    max_ascent: u8,
+    max_descent: u8,
+    diagonal: bool,
+    ascent_cost: usize,
+) -> String {
+    let map = heightmap_from_file(filename);
+    let rules = ClimbRules {
+        max_ascent,
+        max_descent,
+        diagonal,
+        cost: Box::new(move |change| if change > 0 { ascent_cost } else { 1 }),
+    };
+    match map.cheapest_route(&rules) {
+        Some((cost, path)) => format!("cost: {}\n{}", cost, map.render_path(&path)),
+        None => "No route\n".to_string(),
+    }
+}
+
+fn fewest_steps_graphmap(map: &Heightmap) -> i32 {
+    let paths = paths_from_heightmap(map, false);
+    *dijkstra(&paths, map.start, Some(map.end), |_| 1)
+        .get(&map.end)
+        .unwrap()
+}
+
+fn fewest_steps_from_lowest_graphmap(map: &Heightmap) -> i32 {
+    let paths = paths_from_heightmap(map, true);
     let shortest_paths = dijkstra(&paths, map.end, None, |_| 1);
     shortest_paths
         .into_iter()
         .filter_map(|(k, v)| {
-            if *map.elevations.index(k.0, k.1) == 'a' as u8 {
+            if *map.elevations.index(k.0, k.1) == b'a' {
                 Some(v)
             } else {
                 None
@@ -156,9 +303,56 @@ pub fn part_2(filename: &str) -> i32 {
         .unwrap()
 }
 
+#[rstest]
+#[case::test("./test12.txt", 31, 29)]
+#[case::input("./input12.txt", 497, 492)]
+fn test_graphmap(#[case] filename: &str, #[case] part_1: i32, #[case] part_2: i32) {
+    let map = heightmap_from_file(filename);
+    assert_eq!(fewest_steps_graphmap(&map), part_1);
+    assert_eq!(fewest_steps_from_lowest_graphmap(&map), part_2);
+}
+
+/// Times the grid searches against the original Dijkstra over a `GraphMap`.
+pub fn bench(filename: &str) {
+    let map = heightmap_from_file(filename);
+    bench::bench("part 1: GraphMap + Dijkstra", 20, || {
+        fewest_steps_graphmap(&map)
+    });
+    bench::bench("part 1: grid A*", 20, || map.shortest_path());
+    bench::bench("part 1: grid BFS", 20, || {
+        map.search(&ClimbRules::default())
+            .bfs([map.start], |c| c == map.end)
+    });
+    bench::bench("part 2: GraphMap + Dijkstra", 20, || {
+        fewest_steps_from_lowest_graphmap(&map)
+    });
+    bench::bench("part 2: grid multi-source BFS", 20, || {
+        map.shortest_path_from_lowest()
+    });
+}
+
+pub fn part_1(filename: &str) -> usize {
+    heightmap_from_file(filename).shortest_path().unwrap().len() - 1
+}
+
+#[rstest]
+#[case::test("./test12.txt", 31)]
+#[case::input("./input12.txt", 497)]
+fn test_part_1(#[case] filename: &str, #[case] result: usize) {
+    assert_eq!(part_1(filename), result);
+}
+
+pub fn part_2(filename: &str) -> usize {
+    heightmap_from_file(filename)
+        .shortest_path_from_lowest()
+        .unwrap()
+        .len()
+        - 1
+}
+
 #[rstest]
 #[case::test("./test12.txt", 29)]
 #[case::input("./input12.txt", 492)]
-fn test_part_2(#[case] filename: &str, #[case] result: i32) {
+fn test_part_2(#[case] filename: &str, #[case] result: usize) {
     assert_eq!(part_2(filename), result);
 }
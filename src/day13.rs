@@ -1,37 +1,602 @@
+use itertools::Itertools;
 use rstest::rstest;
+#[cfg(feature = "json-packets")]
 use serde_json::{json, Value};
 use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
 
+#[cfg(feature = "json-packets")]
+use crate::bench;
 use crate::fs;
 
-#[derive(Clone, Eq)]
-struct Packet {
-    value: Value,
+/// A packet, or any value nested inside one.
+#[derive(Clone, Debug)]
+enum PacketValue {
+    Int(u32),
+    List(Vec<PacketValue>),
 }
 
-impl PartialEq for Packet {
+impl PartialEq for PacketValue {
     fn eq(&self, other: &Self) -> bool {
-        compare_packets(&self.value, &other.value) == Ordering::Equal
-    }
-
-    fn ne(&self, other: &Self) -> bool {
-        !self.eq(other)
+        compare_packets(self, other) == Ordering::Equal
     }
 }
 
-impl PartialOrd for Packet {
+impl Eq for PacketValue {}
+
+impl PartialOrd for PacketValue {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(compare_packets(&self.value, &other.value))
+        Some(self.cmp(other))
     }
 }
 
-impl Ord for Packet {
+impl Ord for PacketValue {
     fn cmp(&self, other: &Self) -> Ordering {
-        compare_packets(&self.value, &other.value)
+        compare_packets(self, other)
+    }
+}
+
+impl fmt::Display for PacketValue {
+    /// Prints the value in the same compact notation as the puzzle input.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PacketValue::Int(i) => write!(f, "{}", i),
+            PacketValue::List(list) => write_list(f, list),
+        }
+    }
+}
+
+fn write_list(f: &mut fmt::Formatter<'_>, list: &[PacketValue]) -> fmt::Result {
+    write!(f, "[")?;
+    for (i, value) in list.iter().enumerate() {
+        if i != 0 {
+            write!(f, ",")?;
+        }
+        write!(f, "{}", value)?;
+    }
+    write!(f, "]")
+}
+
+/// Parses packets straight from the input bytes, without building intermediate strings.
+struct PacketParser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> PacketParser<'a> {
+    fn parse(s: &'a str) -> Result<PacketValue, String> {
+        let mut parser = PacketParser {
+            bytes: s.as_bytes(),
+            position: 0,
+        };
+        let value = parser.value()?;
+        match parser.bytes.get(parser.position) {
+            None => Ok(value),
+            Some(_) => Err(parser.unexpected()),
+        }
+    }
+
+    fn unexpected(&self) -> String {
+        match self.bytes.get(self.position) {
+            Some(&b) => format!("unexpected '{}' at column {}", b as char, self.position + 1),
+            None => "unexpected end of packet".to_string(),
+        }
+    }
+
+    fn value(&mut self) -> Result<PacketValue, String> {
+        match self.bytes.get(self.position) {
+            Some(b'[') => self.list(),
+            Some(b'0'..=b'9') => self.int(),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn list(&mut self) -> Result<PacketValue, String> {
+        self.position += 1;
+        let mut list = Vec::new();
+        if self.bytes.get(self.position) == Some(&b']') {
+            self.position += 1;
+            return Ok(PacketValue::List(list));
+        }
+        loop {
+            list.push(self.value()?);
+            match self.bytes.get(self.position) {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(PacketValue::List(list));
+                }
+                _ => return Err(self.unexpected()),
+            }
+        }
+    }
+
+    fn int(&mut self) -> Result<PacketValue, String> {
+        let start = self.position;
+        let mut n: u32 = 0;
+        while let Some(&b @ b'0'..=b'9') = self.bytes.get(self.position) {
+            n = n
+                .checked_mul(10)
+                .and_then(|n| n.checked_add((b - b'0') as u32))
+                .ok_or_else(|| format!("integer at column {} is too large", start + 1))?;
+            self.position += 1;
+        }
+        Ok(PacketValue::Int(n))
+    }
+}
+
+#[rstest]
+#[case("[]", PacketValue::List(vec![]))]
+#[case("7", PacketValue::Int(7))]
+#[case(
+    "[1,[20,[]],3]",
+    PacketValue::List(vec![
+        PacketValue::Int(1),
+        PacketValue::List(vec![PacketValue::Int(20), PacketValue::List(vec![])]),
+        PacketValue::Int(3),
+    ])
+)]
+fn test_parse_packet(#[case] s: &str, #[case] expected: PacketValue) {
+    // PartialEq follows the packet ordering, so compare the Debug output to check the structure
+    assert_eq!(
+        format!("{:?}", PacketParser::parse(s).unwrap()),
+        format!("{:?}", expected)
+    );
+}
+
+#[rstest]
+#[case("[]")]
+#[case("[1,[2,[3,[4,[5,6,7]]]],8,9]")]
+#[case("[[],[[]],10]")]
+fn test_display_packet(#[case] s: &str) {
+    assert_eq!(PacketParser::parse(s).unwrap().to_string(), s);
+}
+
+#[rstest]
+#[case("", "unexpected end of packet")]
+#[case("[1,2", "unexpected end of packet")]
+#[case("[1,,2]", "unexpected ',' at column 4")]
+#[case("[1 ,2]", "unexpected ' ' at column 3")]
+#[case("[1]]", "unexpected ']' at column 4")]
+#[case("[-1]", "unexpected '-' at column 2")]
+#[case("[1,99999999999]", "integer at column 4 is too large")]
+fn test_parse_packet_error(#[case] s: &str, #[case] error: &str) {
+    assert_eq!(PacketParser::parse(s), Err(error.to_string()));
+}
+
+/// A whole packet from the distress signal, which is always a list.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Packet {
+    value: PacketValue,
+}
+
+impl FromStr for Packet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match PacketParser::parse(s)? {
+            PacketValue::Int(_) => Err("packet must be a list".to_string()),
+            value => Ok(Packet { value }),
+        }
+    }
+}
+
+fn compare_packets(p1: &PacketValue, p2: &PacketValue) -> Ordering {
+    match (p1, p2) {
+        (PacketValue::Int(i1), PacketValue::Int(i2)) => i1.cmp(i2),
+        (PacketValue::List(l1), PacketValue::List(l2)) => l1.iter().cmp(l2.iter()),
+        // Compare against a one-element list without allocating one
+        (PacketValue::List(l1), PacketValue::Int(_)) => l1.iter().cmp(std::slice::from_ref(p2)),
+        (PacketValue::Int(_), PacketValue::List(l2)) => std::slice::from_ref(p1).iter().cmp(l2),
+    }
+}
+
+#[rstest]
+#[case("[1,1,3,1,1]", "[1,1,5,1,1]", Ordering::Less)]
+#[case("[[1],[2,3,4]]", "[[1],4]", Ordering::Less)]
+#[case("[9]", "[[8,7,6]]", Ordering::Greater)]
+#[case("[[4,4],4,4]", "[[4,4],4,4,4]", Ordering::Less)]
+#[case("[7,7,7,7]", "[7,7,7]", Ordering::Greater)]
+#[case("[]", "[3]", Ordering::Less)]
+#[case("[[[]]]", "[[]]", Ordering::Greater)]
+#[case(
+    "[1,[2,[3,[4,[5,6,7]]]],8,9]",
+    "[1,[2,[3,[4,[5,6,0]]]],8,9]",
+    Ordering::Greater
+)]
+#[case("[[2]]", "[2]", Ordering::Equal)]
+fn test_compare_packets(#[case] p1: &str, #[case] p2: &str, #[case] result: Ordering) {
+    let (p1, p2) = (
+        PacketParser::parse(p1).unwrap(),
+        PacketParser::parse(p2).unwrap(),
+    );
+    assert_eq!(compare_packets(&p1, &p2), result);
+}
+
+/// Deterministically generates random packets from a seed, using SplitMix64. Integers are kept
+/// small so that equal and nearly-equal values come up often.
+#[cfg(test)]
+struct PacketGenerator {
+    state: u64,
+    max_depth: usize,
+    max_width: usize,
+}
+
+#[cfg(test)]
+impl PacketGenerator {
+    fn new(seed: u64, max_depth: usize, max_width: usize) -> Self {
+        PacketGenerator {
+            state: seed,
+            max_depth,
+            max_width,
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    fn value(&mut self, depth: usize) -> PacketValue {
+        if depth >= self.max_depth || self.below(3) == 0 {
+            PacketValue::Int(self.below(11) as u32)
+        } else {
+            self.list(depth)
+        }
+    }
+
+    fn list(&mut self, depth: usize) -> PacketValue {
+        let width = self.below(self.max_width + 1);
+        PacketValue::List((0..width).map(|_| self.value(depth + 1)).collect())
+    }
+
+    fn packet(&mut self) -> Packet {
+        Packet {
+            value: self.list(0),
+        }
+    }
+
+    fn packets(&mut self, count: usize) -> Vec<Packet> {
+        (0..count).map(|_| self.packet()).collect()
+    }
+}
+
+#[test]
+fn test_packet_generator_is_deterministic() {
+    let packets = PacketGenerator::new(13, 4, 4).packets(20);
+    assert_eq!(
+        format!("{:?}", packets),
+        format!("{:?}", PacketGenerator::new(13, 4, 4).packets(20))
+    );
+    assert!(packets
+        .iter()
+        .any(|p| p.value.to_string() != packets[0].value.to_string()));
+}
+
+#[rstest]
+#[case::shallow(1, 1, 6)]
+#[case::deep(2, 6, 2)]
+#[case::wide(3, 3, 8)]
+fn test_ordering_is_total(#[case] seed: u64, #[case] max_depth: usize, #[case] max_width: usize) {
+    let packets = PacketGenerator::new(seed, max_depth, max_width).packets(40);
+    for a in &packets {
+        assert_eq!(a.cmp(a), Ordering::Equal);
+        for b in &packets {
+            let ab = a.cmp(b);
+            assert_eq!(ab, b.cmp(a).reverse(), "{} vs {}", a.value, b.value);
+            assert_eq!(a == b, ab == Ordering::Equal, "{} vs {}", a.value, b.value);
+            for c in &packets {
+                if ab != Ordering::Greater && b <= c {
+                    assert!(a <= c, "{} <= {} <= {}", a.value, b.value, c.value);
+                }
+            }
+        }
+    }
+    let mut sorted = packets.clone();
+    sorted.sort();
+    for pair in sorted.windows(2) {
+        assert!(pair[0] <= pair[1]);
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Side {
+    Left,
+    Right,
+}
+
+#[derive(Debug, PartialEq)]
+enum TraceEvent {
+    Compare {
+        left: String,
+        right: String,
+    },
+    /// An integer on `side` was wrapped in a list to compare it against a list
+    Promote {
+        side: Side,
+        promoted: String,
+    },
+    LeftSmaller,
+    RightSmaller,
+    LeftRanOut,
+    RightRanOut,
+}
+
+impl fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceEvent::Compare { left, right } => write!(f, "Compare {} vs {}", left, right),
+            TraceEvent::Promote { side, promoted } => write!(
+                f,
+                "Mixed types; convert {} to {} and retry comparison",
+                match side {
+                    Side::Left => "left",
+                    Side::Right => "right",
+                },
+                promoted
+            ),
+            TraceEvent::LeftSmaller => {
+                write!(f, "Left side is smaller, so inputs are in the right order")
+            }
+            TraceEvent::RightSmaller => {
+                write!(
+                    f,
+                    "Right side is smaller, so inputs are not in the right order"
+                )
+            }
+            TraceEvent::LeftRanOut => {
+                write!(
+                    f,
+                    "Left side ran out of items, so inputs are in the right order"
+                )
+            }
+            TraceEvent::RightRanOut => write!(
+                f,
+                "Right side ran out of items, so inputs are not in the right order"
+            ),
+        }
+    }
+}
+
+/// One line of a comparison trace. `path` holds the list indices leading to the values being
+/// compared, counting a promoted integer as index 0 of its new list.
+#[derive(Debug, PartialEq)]
+struct TraceStep {
+    depth: usize,
+    path: Vec<usize>,
+    event: TraceEvent,
+}
+
+#[derive(Debug, PartialEq)]
+struct OrderExplanation {
+    ordering: Ordering,
+    steps: Vec<TraceStep>,
+}
+
+impl fmt::Display for OrderExplanation {
+    /// Renders the trace as the indented list used in the puzzle statement.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in &self.steps {
+            writeln!(f, "{}- {}", "  ".repeat(step.depth), step.event)?;
+        }
+        Ok(())
+    }
+}
+
+/// Why `compare_packets` orders `p1` and `p2` the way it does: every pair of values compared on
+/// the way, where integers were promoted to lists, and what finally decided it.
+fn explain_order(p1: &PacketValue, p2: &PacketValue) -> OrderExplanation {
+    let mut steps = Vec::new();
+    let ordering = explain_values(p1, p2, 0, &mut Vec::new(), &mut steps);
+    OrderExplanation { ordering, steps }
+}
+
+fn explain_values(
+    p1: &PacketValue,
+    p2: &PacketValue,
+    depth: usize,
+    path: &mut Vec<usize>,
+    steps: &mut Vec<TraceStep>,
+) -> Ordering {
+    let mut step = |depth, event| {
+        steps.push(TraceStep {
+            depth,
+            path: path.clone(),
+            event,
+        })
+    };
+    step(
+        depth,
+        TraceEvent::Compare {
+            left: p1.to_string(),
+            right: p2.to_string(),
+        },
+    );
+    let ordering = match (p1, p2) {
+        (PacketValue::Int(_), PacketValue::Int(_)) => {
+            let ordering = compare_packets(p1, p2);
+            match ordering {
+                Ordering::Less => step(depth + 1, TraceEvent::LeftSmaller),
+                Ordering::Greater => step(depth + 1, TraceEvent::RightSmaller),
+                Ordering::Equal => {}
+            }
+            ordering
+        }
+        (PacketValue::List(l1), PacketValue::List(l2)) => {
+            explain_lists(l1, l2, depth + 1, path, steps)
+        }
+        (PacketValue::List(l1), PacketValue::Int(_)) => {
+            let promoted = format!("[{}]", p2);
+            step(
+                depth + 1,
+                TraceEvent::Promote {
+                    side: Side::Right,
+                    promoted: promoted.clone(),
+                },
+            );
+            step(
+                depth + 1,
+                TraceEvent::Compare {
+                    left: p1.to_string(),
+                    right: promoted,
+                },
+            );
+            explain_lists(l1, std::slice::from_ref(p2), depth + 2, path, steps)
+        }
+        (PacketValue::Int(_), PacketValue::List(l2)) => {
+            let promoted = format!("[{}]", p1);
+            step(
+                depth + 1,
+                TraceEvent::Promote {
+                    side: Side::Left,
+                    promoted: promoted.clone(),
+                },
+            );
+            step(
+                depth + 1,
+                TraceEvent::Compare {
+                    left: promoted,
+                    right: p2.to_string(),
+                },
+            );
+            explain_lists(std::slice::from_ref(p1), l2, depth + 2, path, steps)
+        }
+    };
+    debug_assert_eq!(ordering, compare_packets(p1, p2));
+    ordering
+}
+
+fn explain_lists(
+    l1: &[PacketValue],
+    l2: &[PacketValue],
+    depth: usize,
+    path: &mut Vec<usize>,
+    steps: &mut Vec<TraceStep>,
+) -> Ordering {
+    for (i, (v1, v2)) in l1.iter().zip(l2).enumerate() {
+        path.push(i);
+        let ordering = explain_values(v1, v2, depth, path, steps);
+        path.pop();
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
     }
+    let ordering = l1.len().cmp(&l2.len());
+    let event = match ordering {
+        Ordering::Less => TraceEvent::LeftRanOut,
+        Ordering::Greater => TraceEvent::RightRanOut,
+        Ordering::Equal => return ordering,
+    };
+    steps.push(TraceStep {
+        depth,
+        path: path.clone(),
+        event,
+    });
+    ordering
+}
+
+#[test]
+fn test_explain_order() {
+    let (p1, p2) = (
+        PacketParser::parse("[[1],[2,3,4]]").unwrap(),
+        PacketParser::parse("[[1],4]").unwrap(),
+    );
+    let explanation = explain_order(&p1, &p2);
+    assert_eq!(explanation.ordering, Ordering::Less);
+    assert_eq!(
+        explanation.steps.last(),
+        Some(&TraceStep {
+            depth: 4,
+            path: vec![1, 0],
+            event: TraceEvent::LeftSmaller
+        })
+    );
+    assert_eq!(
+        explanation.steps[4],
+        TraceStep {
+            depth: 2,
+            path: vec![1],
+            event: TraceEvent::Promote {
+                side: Side::Right,
+                promoted: "[4]".to_string()
+            }
+        }
+    );
+}
+
+pub fn explain(filename: &str) -> String {
+    packets_from_file(filename)
+        .unwrap()
+        .chunks(2)
+        .enumerate()
+        .map(|(i, pair)| {
+            format!(
+                "== Pair {} ==\n{}",
+                i + 1,
+                explain_order(&pair[0].value, &pair[1].value)
+            )
+        })
+        .join("\n")
+}
+
+#[test]
+fn test_explain() {
+    let explanation = explain("./test13.txt");
+    let pairs: Vec<&str> = explanation.split("\n\n").collect();
+    assert_eq!(
+        pairs[1],
+        "== Pair 2 ==\n\
+         - Compare [[1],[2,3,4]] vs [[1],4]\n\
+         \x20 - Compare [1] vs [1]\n\
+         \x20   - Compare 1 vs 1\n\
+         \x20 - Compare [2,3,4] vs 4\n\
+         \x20   - Mixed types; convert right to [4] and retry comparison\n\
+         \x20   - Compare [2,3,4] vs [4]\n\
+         \x20     - Compare 2 vs 4\n\
+         \x20       - Left side is smaller, so inputs are in the right order"
+    );
+    assert_eq!(
+        pairs[2],
+        "== Pair 3 ==\n\
+         - Compare [9] vs [[8,7,6]]\n\
+         \x20 - Compare 9 vs [8,7,6]\n\
+         \x20   - Mixed types; convert left to [9] and retry comparison\n\
+         \x20   - Compare [9] vs [8,7,6]\n\
+         \x20     - Compare 9 vs 8\n\
+         \x20       - Right side is smaller, so inputs are not in the right order"
+    );
+    assert_eq!(
+        pairs[3],
+        "== Pair 4 ==\n\
+         - Compare [[4,4],4,4] vs [[4,4],4,4,4]\n\
+         \x20 - Compare [4,4] vs [4,4]\n\
+         \x20   - Compare 4 vs 4\n\
+         \x20   - Compare 4 vs 4\n\
+         \x20 - Compare 4 vs 4\n\
+         \x20 - Compare 4 vs 4\n\
+         \x20 - Left side ran out of items, so inputs are in the right order"
+    );
+    assert_eq!(
+        pairs[6],
+        "== Pair 7 ==\n\
+         - Compare [[[]]] vs [[]]\n\
+         \x20 - Compare [[]] vs []\n\
+         \x20   - Right side ran out of items, so inputs are not in the right order"
+    );
 }
 
-fn compare_packets(p1: &Value, p2: &Value) -> Ordering {
+/// The original comparison over `serde_json` values, kept to check and time the `PacketValue`
+/// version against.
+#[cfg(feature = "json-packets")]
+fn compare_json_packets(p1: &Value, p2: &Value) -> Ordering {
     if p1.is_i64() && p2.is_i64() {
         let p1i = p1.as_i64().unwrap();
         let p2i = p2.as_i64().unwrap();
@@ -39,62 +604,106 @@ fn compare_packets(p1: &Value, p2: &Value) -> Ordering {
     } else if p1.is_array() && p2.is_array() {
         let p1a = p1.as_array().unwrap();
         let p2a = p2.as_array().unwrap();
-        for (i, value) in p1a.into_iter().enumerate() {
+        for (i, value) in p1a.iter().enumerate() {
             if p2a.len() <= i {
                 return Ordering::Greater;
             }
-            let cmp = compare_packets(value, &p2a[i]);
+            let cmp = compare_json_packets(value, &p2a[i]);
             if cmp != Ordering::Equal {
                 return cmp;
             }
         }
         p1a.len().cmp(&p2a.len())
     } else if p1.is_array() && p2.is_i64() {
-        compare_packets(p1, &json!([p2]))
+        compare_json_packets(p1, &json!([p2]))
     } else if p2.is_array() && p1.is_i64() {
-        compare_packets(&json!([p1]), p2)
+        compare_json_packets(&json!([p1]), p2)
     } else {
         panic!("Expected each value to be array or integer");
     }
 }
 
+#[cfg(feature = "json-packets")]
 #[rstest]
-#[case(json!([1,1,3,1,1]), json!([1,1,5,1,1]), Ordering::Less)]
-#[case(json!([[1],[2,3,4]]), json!([[1],4]), Ordering::Less)]
-#[case(json!([9]), json!([[8,7,6]]), Ordering::Greater)]
-#[case(json!([[4,4],4,4]), json!([[4,4],4,4,4]), Ordering::Less)]
-#[case(json!([7,7,7,7]), json!([7,7,7]), Ordering::Greater)]
-#[case(json!([]), json!([3]), Ordering::Less)]
-#[case(json!([[[]]]), json!([[]]), Ordering::Greater)]
-#[case(json!([1,[2,[3,[4,[5,6,7]]]],8,9]), json!([1,[2,[3,[4,[5,6,0]]]],8,9]), Ordering::Greater)]
-fn test_compare_packets(#[case] p1: Value, #[case] p2: Value, #[case] result: Ordering) {
-    assert_eq!(compare_packets(&p1, &p2), result);
+#[case("./test13.txt")]
+#[case("./input13.txt")]
+fn test_compare_json_packets(#[case] filename: &str) {
+    let lines: Vec<String> = fs::read_lines(filename)
+        .unwrap()
+        .map_while(Result::ok)
+        .filter(|s| !s.is_empty())
+        .collect();
+    for pair in lines.chunks(2) {
+        let (p1, p2) = (
+            PacketParser::parse(&pair[0]).unwrap(),
+            PacketParser::parse(&pair[1]).unwrap(),
+        );
+        let (j1, j2) = (
+            serde_json::from_str::<Value>(&pair[0]).unwrap(),
+            serde_json::from_str::<Value>(&pair[1]).unwrap(),
+        );
+        assert_eq!(compare_packets(&p1, &p2), compare_json_packets(&j1, &j2));
+    }
+}
+
+/// Times parsing and comparing every pair with `PacketValue` against `serde_json`.
+#[cfg(feature = "json-packets")]
+pub fn bench(filename: &str) {
+    let lines: Vec<String> = fs::read_lines(filename)
+        .unwrap()
+        .map_while(Result::ok)
+        .filter(|s| !s.is_empty())
+        .collect();
+    bench::bench("PacketValue", 100, || {
+        lines
+            .chunks(2)
+            .map(|pair| {
+                let p1 = PacketParser::parse(&pair[0]).unwrap();
+                let p2 = PacketParser::parse(&pair[1]).unwrap();
+                compare_packets(&p1, &p2)
+            })
+            .filter(|&o| o == Ordering::Less)
+            .count()
+    });
+    bench::bench("serde_json::Value", 100, || {
+        lines
+            .chunks(2)
+            .map(|pair| {
+                let j1 = serde_json::from_str::<Value>(&pair[0]).unwrap();
+                let j2 = serde_json::from_str::<Value>(&pair[1]).unwrap();
+                compare_json_packets(&j1, &j2)
+            })
+            .filter(|&o| o == Ordering::Less)
+            .count()
+    });
+}
+
+/// Every packet in the file, in order, skipping the blank lines between pairs.
+fn packets_from_file(filename: &str) -> Result<Vec<Packet>, String> {
+    fs::read_lines(filename)
+        .unwrap()
+        .map_while(Result::ok)
+        .enumerate()
+        .filter(|(_, s)| !s.is_empty())
+        .map(|(i, s)| s.parse().map_err(|e| format!("line {}: {}", i + 1, e)))
+        .collect()
+}
+
+#[test]
+fn test_packets_from_file() {
+    let packets = packets_from_file("./test13.txt").unwrap();
+    assert_eq!(packets.len(), 16);
+    assert_eq!(packets[15], "[1,[2,[3,[4,[5,6,0]]]],8,9]".parse().unwrap());
 }
 
 pub fn part_1(filename: &str) -> usize {
-    let mut result = 0;
-    let mut i = 1;
-    let mut lines = fs::read_lines(filename).unwrap().filter_map(|r| r.ok());
-    loop {
-        let pair: Vec<Value> = lines
-            .by_ref()
-            .take(3)
-            .filter(|s| s.len() > 0)
-            .map(|s| serde_json::from_str::<Value>(&s).unwrap())
-            .inspect(|j| assert!(j.is_array()))
-            .collect();
-        if pair.len() < 2 {
-            break;
-        }
-        match compare_packets(&pair[0], &pair[1]) {
-            Ordering::Greater => {}
-            _ => {
-                result += i;
-            }
-        }
-        i += 1;
-    }
-    result
+    packets_from_file(filename)
+        .unwrap()
+        .chunks(2)
+        .enumerate()
+        .filter(|(_, pair)| pair[0] <= pair[1])
+        .map(|(i, _)| i + 1)
+        .sum()
 }
 
 #[rstest]
@@ -104,37 +713,134 @@ fn test_part_1(#[case] filename: &str, #[case] result: usize) {
     assert_eq!(part_1(filename), result);
 }
 
-pub fn part_2(filename: &str) -> usize {
-    let divider_packets = [
-        Packet {
-            value: json!([[2]]),
-        },
-        Packet {
-            value: json!([[6]]),
-        },
-    ];
-    let mut packets: Vec<Packet> = fs::read_lines(filename)
-        .unwrap()
-        .filter_map(|r| r.ok())
-        .filter(|s| s.len() > 0)
-        .map(|s| Packet {
-            value: serde_json::from_str::<Value>(&s).unwrap(),
-        })
-        .chain(divider_packets.iter().cloned())
+/// The 1-based position of each divider once they're added after `packets` and everything is
+/// sorted. The sort is stable and dividers are tracked by position rather than by equality, so a
+/// packet that merely compares equal to a divider (like `[2]` and `[[2]]`) is never mistaken
+/// for it and always stays ahead of it.
+#[cfg(test)]
+fn divider_positions(packets: Vec<Packet>, dividers: &[Packet]) -> Vec<usize> {
+    let mut tagged: Vec<(Packet, Option<usize>)> = packets
+        .into_iter()
+        .map(|p| (p, None))
+        .chain(
+            dividers
+                .iter()
+                .cloned()
+                .enumerate()
+                .map(|(i, d)| (d, Some(i))),
+        )
         .collect();
+    tagged.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut positions = vec![0; dividers.len()];
+    for (position, (_, tag)) in tagged.into_iter().enumerate() {
+        if let Some(i) = tag {
+            positions[i] = position + 1;
+        }
+    }
+    positions
+}
 
-    packets.sort();
-    packets
-        .into_iter()
+#[rstest]
+#[case(7)]
+#[case(8)]
+#[case(9)]
+fn test_divider_positions(#[case] seed: u64) {
+    let dividers: Vec<Packet> = ["[[2]]", "[[6]]", "[[2]]"]
+        .iter()
+        .map(|s| s.parse().unwrap())
+        .collect();
+    let mut packets = PacketGenerator::new(seed, 3, 3).packets(200);
+    // Mix in packets that are equal to the dividers without being identical to them
+    for (i, s) in ["[2]", "[[[2]]]", "[6]", "[[6]]", "[[2]]"]
+        .iter()
         .enumerate()
-        .filter_map(|(i, p)| {
-            if p == divider_packets[0] || p == divider_packets[1] {
-                Some(i)
-            } else {
-                None
+    {
+        packets.insert(i * 40, s.parse().unwrap());
+    }
+    let positions = divider_positions(packets.clone(), &dividers);
+    for (i, divider) in dividers.iter().enumerate() {
+        // Everything smaller, plus everything equal that came before it, ends up ahead
+        let ahead = packets.iter().filter(|p| *p <= divider).count()
+            + dividers[..i].iter().filter(|d| *d <= divider).count()
+            + dividers[i + 1..].iter().filter(|d| *d < divider).count();
+        assert_eq!(positions[i], ahead + 1);
+    }
+    assert!(positions[0] < positions[2]);
+}
+
+/// The same ranks as `divider_positions`, found without sorting: one pass over `packets` counts
+/// how many sort ahead of each probe, then the probes are compared among themselves.
+fn probe_ranks<I>(packets: I, probes: &[Packet]) -> Vec<usize>
+where
+    I: IntoIterator<Item = Packet>,
+{
+    let mut ranks = vec![1; probes.len()];
+    for packet in packets {
+        for (rank, probe) in ranks.iter_mut().zip(probes) {
+            if packet <= *probe {
+                *rank += 1;
+            }
+        }
+    }
+    for (i, probe) in probes.iter().enumerate() {
+        for (j, other) in probes.iter().enumerate() {
+            // Equal probes keep their order, just as they would in a stable sort
+            if other < probe || (other == probe && j < i) {
+                ranks[i] += 1;
             }
+        }
+    }
+    ranks
+}
+
+#[rstest]
+#[case::no_probes(&[])]
+#[case::dividers(&["[[2]]", "[[6]]"])]
+#[case::equal_probes(&["[[2]]", "[2]", "[[[2]]]", "[]", "[10,10,10]"])]
+fn test_probe_ranks(#[case] probes: &[&str]) {
+    let probes: Vec<Packet> = probes.iter().map(|s| s.parse().unwrap()).collect();
+    for seed in 0..5 {
+        let packets = PacketGenerator::new(seed, 3, 3).packets(100);
+        assert_eq!(
+            probe_ranks(packets.clone(), &probes),
+            divider_positions(packets, &probes)
+        );
+    }
+}
+
+/// Each probe's rank among the packets in the file, followed by the decoder key they'd give.
+pub fn ranks(filename: &str, probes: &[String]) -> String {
+    let probes: Vec<Packet> = probes
+        .iter()
+        .map(|s| {
+            s.parse()
+                .unwrap_or_else(|e| panic!("Invalid probe {}: {}", s, e))
         })
-        .map(|i| i + 1)
+        .collect();
+    let ranks = probe_ranks(packets_from_file(filename).unwrap(), &probes);
+    let mut result = String::new();
+    for (probe, rank) in probes.iter().zip(&ranks) {
+        result.push_str(&format!("{} {}\n", probe.value, rank));
+    }
+    result.push_str(&format!(
+        "decoder key: {}\n",
+        ranks.iter().product::<usize>()
+    ));
+    result
+}
+
+#[test]
+fn test_ranks() {
+    assert_eq!(
+        ranks("./test13.txt", &["[[2]]".to_string(), "[[6]]".to_string()]),
+        "[[2]] 10\n[[6]] 14\ndecoder key: 140\n"
+    );
+}
+
+pub fn part_2(filename: &str) -> usize {
+    let divider_packets: [Packet; 2] = ["[[2]]".parse().unwrap(), "[[6]]".parse().unwrap()];
+    probe_ranks(packets_from_file(filename).unwrap(), &divider_packets)
+        .into_iter()
         .product()
 }
 
@@ -1,34 +1,58 @@
 use rstest::rstest;
 
+use crate::bench;
 use crate::fs;
 use crate::vec2d::Vec2d;
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Cell {
+    Air,
+    Rock,
+    Sand,
+    Source,
+}
+
+impl Cell {
+    fn is_solid(self) -> bool {
+        matches!(self, Cell::Rock | Cell::Sand)
+    }
+
+    fn symbol(self) -> char {
+        match self {
+            Cell::Air => '.',
+            Cell::Rock => '#',
+            Cell::Sand => 'o',
+            Cell::Source => '+',
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
 struct Cave {
-    map: Vec2d<bool>,
+    map: Vec2d<Cell>,
     x_min: usize,
     y_min: usize,
     x_max: usize,
     y_max: usize,
 }
 
+impl Cave {
+    /// Draws the cave the way the puzzle does, one line per row.
+    fn render(&self) -> String {
+        (0..self.map.row_count)
+            .map(|i| {
+                let mut row: String = self.map.row(i).iter().map(|c| c.symbol()).collect();
+                row.push('\n');
+                row
+            })
+            .collect()
+    }
+}
+
 impl std::fmt::Debug for Cave {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut str = String::from("map: \n");
-        for i in 0..self.map.row_count {
-            str.push_str(
-                self.map
-                    .row(i)
-                    .iter()
-                    .map(|b| match b {
-                        true => '#',
-                        false => '.',
-                    })
-                    .collect::<String>()
-                    .as_str(),
-            );
-            str.push_str("\n");
-        }
+        str.push_str(&self.render());
         str.push_str(&format!(", x: {}-{}", self.x_min, self.x_max));
         str.push_str(&format!(", y: {}-{}", self.y_min, self.y_max));
         write!(f, "Cave {{ {} }}", str)
@@ -37,43 +61,78 @@ impl std::fmt::Debug for Cave {
 
 type Coord = (usize, usize);
 
-fn cave_from_file(filename: &str, include_floor: bool) -> Cave {
-    let (mut x_range, mut y_range) = (usize::MAX..=0, usize::MAX..=0);
+/// Where sand is poured from and what the cave looks like below the scan. Each source may stop
+/// after a number of grains, and with a `floor_depth` there is an endless floor that many rows
+/// below the lowest rock.
+struct CaveConfig {
+    sources: Vec<(Coord, Option<usize>)>,
+    floor_depth: Option<usize>,
+}
+
+impl Default for CaveConfig {
+    /// The puzzle's part 1: a single endless source at `(500, 0)` and no floor.
+    fn default() -> Self {
+        CaveConfig {
+            sources: vec![((500, 0), None)],
+            floor_depth: None,
+        }
+    }
+}
+
+impl CaveConfig {
+    fn with_floor() -> Self {
+        CaveConfig {
+            floor_depth: Some(2),
+            ..Default::default()
+        }
+    }
+}
+
+fn cave_from_file(filename: &str, config: &CaveConfig) -> Cave {
     let rocks: Vec<Vec<Coord>> = fs::read_lines(filename)
         .unwrap()
-        .filter_map(|r| r.ok())
-        .chain(["500,0".to_string()])
+        .map_while(Result::ok)
         .map(|l| {
             l.split(" -> ")
                 .map(|s| {
                     let (x_str, y_str) = s.split_once(',').unwrap();
-                    let (x, y) = (x_str.parse().unwrap(), y_str.parse().unwrap());
-                    x_range = *x_range.start().min(&x)..=*x_range.end().max(&x);
-                    y_range = *y_range.start().min(&y)..=*y_range.end().max(&y);
-                    (x, y)
+                    (x_str.parse().unwrap(), y_str.parse().unwrap())
                 })
                 .collect()
         })
         .collect();
-    if include_floor {
-        y_range = *y_range.start()..=*y_range.end() + 2;
-        let y_count = y_range.clone().count();
-        x_range = *x_range.start().min(&(500 - y_count))..=*x_range.end().max(&(500 + y_count));
-    }
-    let (row_count, col_count) = (y_range.clone().count(), x_range.clone().count());
-    let mut vec = vec![false; row_count * col_count];
-    if include_floor {
-        for i in (vec.len() - col_count)..vec.len() {
-            vec[i] = true;
+    let points = || {
+        rocks
+            .iter()
+            .flatten()
+            .chain(config.sources.iter().map(|(source, _)| source))
+    };
+    let mut x_min = points().map(|p| p.0).min().unwrap();
+    let mut x_max = points().map(|p| p.0).max().unwrap();
+    let y_min = points().map(|p| p.1).min().unwrap();
+    let mut y_max = points().map(|p| p.1).max().unwrap();
+    if let Some(depth) = config.floor_depth {
+        y_max += depth;
+        // Sand can't spread further sideways than it falls
+        let y_count = y_max - y_min + 1;
+        for &((x, _), _) in &config.sources {
+            x_min = x_min.min(x.saturating_sub(y_count));
+            x_max = x_max.max(x + y_count);
         }
     }
+    let (row_count, col_count) = (y_max - y_min + 1, x_max - x_min + 1);
+    let mut vec = vec![Cell::Air; row_count * col_count];
+    if config.floor_depth.is_some() {
+        let floor = vec.len() - col_count;
+        vec[floor..].fill(Cell::Rock);
+    }
     let map = Vec2d::new(vec, row_count, col_count);
     let mut cave = Cave {
         map,
-        x_min: *x_range.start(),
-        y_min: *y_range.start(),
-        x_max: *x_range.end(),
-        y_max: *y_range.end(),
+        x_min,
+        y_min,
+        x_max,
+        y_max,
     };
     for segments in rocks {
         let mut startpoint = segments[0];
@@ -90,21 +149,27 @@ fn cave_from_file(filename: &str, include_floor: bool) -> Cave {
             };
             for x in x_range {
                 for y in y_range.clone() {
-                    *cave.map.index_mut(y - cave.y_min, x - cave.x_min) = true;
+                    *cave.map.index_mut(y - cave.y_min, x - cave.x_min) = Cell::Rock;
                 }
             }
             startpoint = endpoint;
         }
     }
+    for &((x, y), _) in &config.sources {
+        *cave.map.index_mut(y - cave.y_min, x - cave.x_min) = Cell::Source;
+    }
     cave
 }
 
 #[test]
 fn test_cave_from_file() {
     assert_eq!(
-        format!("{:?}", cave_from_file("./test14.txt", false)),
+        format!(
+            "{:?}",
+            cave_from_file("./test14.txt", &CaveConfig::default())
+        ),
         "Cave { map: \n\
-         ......#...\n\
+         ......+...\n\
          ..........\n\
          ..........\n\
          ..........\n\
@@ -121,9 +186,12 @@ fn test_cave_from_file() {
 #[test]
 fn test_cave_from_file_with_floor() {
     assert_eq!(
-        format!("{:?}", cave_from_file("./test14.txt", true)),
+        format!(
+            "{:?}",
+            cave_from_file("./test14.txt", &CaveConfig::with_floor())
+        ),
         "Cave { map: \n\
-         ............#............\n\
+         ............+............\n\
          .........................\n\
          .........................\n\
          .........................\n\
@@ -148,7 +216,7 @@ enum SandState {
 fn sand_state(y: usize, x: usize, cave: &Cave) -> SandState {
     if y > cave.y_max || x > cave.x_max || y < cave.y_min || x < cave.x_min {
         SandState::OffEdge
-    } else if *cave.map.index(y - cave.y_min, x - cave.x_min) {
+    } else if cave.map.index(y - cave.y_min, x - cave.x_min).is_solid() {
         SandState::Obstructed
     } else {
         SandState::Freefall
@@ -158,17 +226,17 @@ fn sand_state(y: usize, x: usize, cave: &Cave) -> SandState {
 fn fall_until(mut y: usize, mut x: usize, cave: &Cave) -> Option<Coord> {
     loop {
         y += 1;
-        match sand_state(y, x, &cave) {
+        match sand_state(y, x, cave) {
             SandState::Freefall => continue,
             SandState::OffEdge => return None,
             SandState::Obstructed => {
                 x -= 1;
-                match sand_state(y, x, &cave) {
+                match sand_state(y, x, cave) {
                     SandState::Freefall => continue,
                     SandState::OffEdge => return None,
                     SandState::Obstructed => {
                         x += 2;
-                        match sand_state(y, x, &cave) {
+                        match sand_state(y, x, cave) {
                             SandState::Freefall => continue,
                             SandState::OffEdge => return None,
                             SandState::Obstructed => {
@@ -182,20 +250,249 @@ fn fall_until(mut y: usize, mut x: usize, cave: &Cave) -> Option<Coord> {
     }
 }
 
-pub fn part_1(filename: &str) -> usize {
-    let mut cave = cave_from_file(filename, false);
-    for i in 0.. {
-        let (x, y) = (500, 0);
-        match fall_until(y, x, &cave) {
-            Some((x, y)) => {
-                *cave.map.index_mut(y - cave.y_min, x - cave.x_min) = true;
+fn pour_restarting(cave: &mut Cave) -> usize {
+    let mut grains = 0;
+    while let Some((x, y)) = fall_until(0, 500, cave) {
+        *cave.map.index_mut(y - cave.y_min, x - cave.x_min) = Cell::Sand;
+        grains += 1;
+        if (x, y) == (500, 0) {
+            break;
+        }
+    }
+    grains
+}
+
+enum Step {
+    Fall(Coord),
+    Rest,
+    OffEdge,
+}
+
+fn step(x: usize, y: usize, cave: &Cave) -> Step {
+    for next_x in [x, x.wrapping_sub(1), x + 1] {
+        match sand_state(y + 1, next_x, cave) {
+            SandState::Freefall => return Step::Fall((next_x, y + 1)),
+            SandState::OffEdge => return Step::OffEdge,
+            SandState::Obstructed => continue,
+        }
+    }
+    Step::Rest
+}
+
+/// Drops one grain down `path` and returns whether it came to rest, leaving `path` ready for the
+/// next grain: every grain follows the previous one right up to where that one landed, so rather
+/// than starting over at the source it carries on from the square before. Sand from other sources
+/// can only ever cover the end of a path, so any covered squares are dropped first.
+fn drop_grain(cave: &mut Cave, path: &mut Vec<Coord>) -> bool {
+    while let Some(&(x, y)) = path.last() {
+        if cave.map.index(y - cave.y_min, x - cave.x_min).is_solid() {
+            path.pop();
+            continue;
+        }
+        match step(x, y, cave) {
+            Step::Fall(next) => path.push(next),
+            Step::OffEdge => return false,
+            Step::Rest => {
+                *cave.map.index_mut(y - cave.y_min, x - cave.x_min) = Cell::Sand;
+                path.pop();
+                return true;
             }
-            None => {
-                return i;
+        }
+    }
+    false
+}
+
+/// Pours a grain from each source in turn until none of them can add any more sand, because
+/// they're buried, have poured their limit or are losing sand off the edge. Returns how many
+/// grains came to rest from each source.
+fn pour(cave: &mut Cave, sources: &[(Coord, Option<usize>)]) -> Vec<usize> {
+    let mut paths: Vec<Vec<Coord>> = sources.iter().map(|&(source, _)| vec![source]).collect();
+    let mut grains = vec![0; sources.len()];
+    let mut settling = true;
+    while settling {
+        settling = false;
+        for ((path, count), &(_, limit)) in paths.iter_mut().zip(&mut grains).zip(sources) {
+            if limit.is_none_or(|limit| *count < limit) && drop_grain(cave, path) {
+                *count += 1;
+                settling = true;
             }
         }
     }
-    panic!("Unexpectedly broke loop");
+    grains
+}
+
+fn pour_with(filename: &str, config: &CaveConfig) -> Vec<usize> {
+    pour(&mut cave_from_file(filename, config), &config.sources)
+}
+
+#[rstest]
+#[case::deeper_floor(CaveConfig { floor_depth: Some(4), ..Default::default() }, vec![133])]
+#[case::limited(CaveConfig { sources: vec![((500, 0), Some(10))], floor_depth: None }, vec![10])]
+#[case::two_sources(
+    CaveConfig { sources: vec![((500, 0), None), ((498, 0), None)], floor_depth: None },
+    vec![22, 2]
+)]
+#[case::two_sources_with_floor(
+    CaveConfig { sources: vec![((500, 0), None), ((498, 0), None)], floor_depth: Some(2) },
+    vec![67, 47]
+)]
+#[case::two_sources_limited(
+    CaveConfig { sources: vec![((500, 0), Some(5)), ((503, 0), None)], floor_depth: None },
+    vec![5, 0]
+)]
+fn test_pour_with(#[case] config: CaveConfig, #[case] result: Vec<usize>) {
+    assert_eq!(pour_with("./test14.txt", &config), result);
+}
+
+#[rstest]
+#[case::part_1(
+    CaveConfig::default(),
+    "......+...\n\
+     ..........\n\
+     ......o...\n\
+     .....ooo..\n\
+     ....#ooo##\n\
+     ...o#ooo#.\n\
+     ..###ooo#.\n\
+     ....oooo#.\n\
+     .o.ooooo#.\n\
+     #########.\n"
+)]
+#[case::part_2(
+    CaveConfig::with_floor(),
+    "............o............\n\
+     ...........ooo...........\n\
+     ..........ooooo..........\n\
+     .........ooooooo.........\n\
+     ........oo#ooo##o........\n\
+     .......ooo#ooo#ooo.......\n\
+     ......oo###ooo#oooo......\n\
+     .....oooo.oooo#ooooo.....\n\
+     ....oooooooooo#oooooo....\n\
+     ...ooo#########ooooooo...\n\
+     ..ooooo.......ooooooooo..\n\
+     #########################\n"
+)]
+#[case::limited(
+    CaveConfig { sources: vec![((500, 0), Some(5))], floor_depth: None },
+    "......+...\n\
+     ..........\n\
+     ..........\n\
+     ..........\n\
+     ....#...##\n\
+     ....#...#.\n\
+     ..###...#.\n\
+     ......o.#.\n\
+     ....oooo#.\n\
+     #########.\n"
+)]
+fn test_pour_render(#[case] config: CaveConfig, #[case] result: &str) {
+    let mut cave = cave_from_file("./test14.txt", &config);
+    pour(&mut cave, &config.sources);
+    assert_eq!(cave.render(), result);
+}
+
+/// Pours from each source given as `x,y` or `x,y:limit`, with an endless floor `floor_depth` rows
+/// below the lowest rock if there is one.
+pub fn pour_from(filename: &str, floor_depth: Option<usize>, sources: &[String]) -> String {
+    let sources = sources
+        .iter()
+        .map(|s| {
+            let (source, limit) = match s.split_once(':') {
+                Some((source, limit)) => (source, Some(limit.parse().unwrap())),
+                None => (s.as_str(), None),
+            };
+            let (x, y) = source.split_once(',').unwrap();
+            ((x.parse().unwrap(), y.parse().unwrap()), limit)
+        })
+        .collect();
+    let config = CaveConfig {
+        sources,
+        floor_depth,
+    };
+    let grains = pour_with(filename, &config);
+    let mut result = String::new();
+    for (((x, y), _), count) in config.sources.iter().zip(&grains) {
+        result.push_str(&format!("{},{}: {}\n", x, y, count));
+    }
+    result.push_str(&format!("total: {}\n", grains.iter().sum::<usize>()));
+    result
+}
+
+#[test]
+fn test_pour_from() {
+    assert_eq!(
+        pour_from(
+            "./test14.txt",
+            Some(2),
+            &["500,0".to_string(), "497,2:3".to_string()]
+        ),
+        "500,0: 90\n497,2: 3\ntotal: 93\n"
+    );
+}
+
+/// Part 2 without dropping any grains: with a floor, sand ends up in every open square that
+/// can be reached from one of the three squares above it, so the pile can be filled a row at a
+/// time.
+fn fill_triangle(cave: &Cave) -> usize {
+    let mut row = vec![false; cave.map.col_count];
+    row[500 - cave.x_min] = true;
+    let mut grains = 1;
+    for i in 1..cave.map.row_count {
+        let above = row;
+        row = (0..cave.map.col_count)
+            .map(|j| {
+                !cave.map.index(i, j).is_solid()
+                    && above[j.saturating_sub(1)..(j + 2).min(above.len())]
+                        .iter()
+                        .any(|&sand| sand)
+            })
+            .collect();
+        grains += row.iter().filter(|&&sand| sand).count();
+    }
+    grains
+}
+
+#[rstest]
+#[case::test("./test14.txt", 24, 93)]
+#[case::input("./input14.txt", 610, 27194)]
+fn test_pour_strategies(#[case] filename: &str, #[case] part_1: usize, #[case] part_2: usize) {
+    assert_eq!(
+        pour_restarting(&mut cave_from_file(filename, &CaveConfig::default())),
+        part_1
+    );
+    assert_eq!(
+        pour_restarting(&mut cave_from_file(filename, &CaveConfig::with_floor())),
+        part_2
+    );
+    assert_eq!(
+        fill_triangle(&cave_from_file(filename, &CaveConfig::with_floor())),
+        part_2
+    );
+}
+
+/// Times resuming each grain from the previous grain's path, and filling the part 2 triangle row
+/// by row, against restarting every grain from the source.
+pub fn bench(filename: &str) {
+    let cave = cave_from_file(filename, &CaveConfig::default());
+    bench::bench("part 1: restarting each grain", 20, || {
+        pour_restarting(&mut cave.clone())
+    });
+    bench::bench("part 1: resuming previous path", 20, || {
+        pour(&mut cave.clone(), &[((500, 0), None)])
+    });
+    let cave = cave_from_file(filename, &CaveConfig::with_floor());
+    bench::bench("part 2: restarting each grain", 5, || {
+        pour_restarting(&mut cave.clone())
+    });
+    bench::bench("part 2: resuming previous path", 5, || {
+        pour(&mut cave.clone(), &[((500, 0), None)])
+    });
+    bench::bench("part 2: filling row by row", 5, || fill_triangle(&cave));
+}
+
+pub fn part_1(filename: &str) -> usize {
+    pour_with(filename, &CaveConfig::default())[0]
 }
 
 #[rstest]
@@ -206,20 +503,7 @@ fn test_part_1(#[case] filename: &str, #[case] result: usize) {
 }
 
 pub fn part_2(filename: &str) -> usize {
-    let mut cave = cave_from_file(filename, true);
-    for i in 0.. {
-        let (x, y) = (500, 0);
-        match fall_until(y, x, &cave) {
-            Some((x, y)) => {
-                if (x, y) == (500, 0) {
-                    return i + 1;
-                }
-                *cave.map.index_mut(y - cave.y_min, x - cave.x_min) = true;
-            }
-            None => panic!("Whoops! Fell off the edge"),
-        }
-    }
-    panic!("Broke loop unexpectedly");
+    pour_with(filename, &CaveConfig::with_floor())[0]
 }
 
 #[rstest]
@@ -0,0 +1,240 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, VecDeque},
+};
+
+use crate::vec2d::Vec2d;
+
+/// A `(row, column)` position in a `Vec2d`.
+pub type Coord = (usize, usize);
+
+/// Shortest-path searches over the squares of a `Vec2d`, stepping between orthogonal (and
+/// optionally diagonal) neighbours wherever `can_move(from, to)` allows it.
+pub struct GridSearch<'a, T, F>
+where
+    T: Copy,
+{
+    grid: &'a Vec2d<T>,
+    can_move: F,
+    diagonal: bool,
+}
+
+impl<'a, T, F> GridSearch<'a, T, F>
+where
+    T: Copy,
+    F: Fn(Coord, Coord) -> bool,
+{
+    pub fn new(grid: &'a Vec2d<T>, can_move: F) -> Self {
+        Self {
+            grid,
+            can_move,
+            diagonal: false,
+        }
+    }
+
+    pub fn with_diagonals(self) -> Self {
+        Self {
+            diagonal: true,
+            ..self
+        }
+    }
+
+    pub fn neighbours(&self, (i, j): Coord) -> impl Iterator<Item = Coord> + '_ {
+        let (up, left) = (i.wrapping_sub(1), j.wrapping_sub(1));
+        let orthogonal = [(up, j), (i + 1, j), (i, left), (i, j + 1)];
+        let diagonal = [(up, left), (up, j + 1), (i + 1, left), (i + 1, j + 1)];
+        orthogonal
+            .into_iter()
+            .chain(diagonal.into_iter().filter(move |_| self.diagonal))
+            .filter(|&(row, col)| row < self.grid.row_count && col < self.grid.col_count)
+            .filter(move |&to| (self.can_move)((i, j), to))
+    }
+
+    fn blank<U: Copy>(&self, value: U) -> Vec2d<U> {
+        let (rows, cols) = (self.grid.row_count, self.grid.col_count);
+        Vec2d::new(vec![value; rows * cols], rows, cols)
+    }
+
+    /// Walks `came_from` back from `end` to a square that came from itself, i.e. a start.
+    fn reconstruct(came_from: &Vec2d<Option<Coord>>, end: Coord) -> Vec<Coord> {
+        let mut path = vec![end];
+        let mut current = end;
+        while let Some(previous) = *came_from.index(current.0, current.1) {
+            if previous == current {
+                break;
+            }
+            path.push(previous);
+            current = previous;
+        }
+        path.reverse();
+        path
+    }
+
+    /// Breadth-first search outwards from every square in `starts` at once. Returns a shortest
+    /// path from whichever start is nearest to the first square satisfying `is_goal`.
+    pub fn bfs<I, G>(&self, starts: I, is_goal: G) -> Option<Vec<Coord>>
+    where
+        I: IntoIterator<Item = Coord>,
+        G: Fn(Coord) -> bool,
+    {
+        let mut came_from = self.blank(None);
+        let mut queue = VecDeque::new();
+        for start in starts {
+            let visited = came_from.index_mut(start.0, start.1);
+            if visited.is_none() {
+                *visited = Some(start);
+                queue.push_back(start);
+            }
+        }
+        while let Some(current) = queue.pop_front() {
+            if is_goal(current) {
+                return Some(Self::reconstruct(&came_from, current));
+            }
+            for next in self.neighbours(current) {
+                let visited = came_from.index_mut(next.0, next.1);
+                if visited.is_none() {
+                    *visited = Some(current);
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+
+    /// A* from `start` to `goal` counting every step as 1, guided by the Manhattan distance to
+    /// `goal` (or the Chebyshev distance when diagonal steps are allowed).
+    pub fn astar(&self, start: Coord, goal: Coord) -> Option<Vec<Coord>> {
+        let heuristic = |(i, j): Coord| {
+            let (rows, cols) = (i.abs_diff(goal.0), j.abs_diff(goal.1));
+            match self.diagonal {
+                true => rows.max(cols),
+                false => rows + cols,
+            }
+        };
+        self.best_first(start, goal, |_, _| 1, heuristic)
+            .map(|(_, path)| path)
+    }
+
+    /// Dijkstra from `start` to `goal` where each step costs `cost(from, to)`. Returns the total
+    /// cost along with the path.
+    pub fn cheapest_path<C>(
+        &self,
+        start: Coord,
+        goal: Coord,
+        cost: C,
+    ) -> Option<(usize, Vec<Coord>)>
+    where
+        C: Fn(Coord, Coord) -> usize,
+    {
+        self.best_first(start, goal, cost, |_| 0)
+    }
+
+    /// A* in general; with a zero `heuristic` this is Dijkstra's algorithm.
+    fn best_first<C, H>(
+        &self,
+        start: Coord,
+        goal: Coord,
+        cost: C,
+        heuristic: H,
+    ) -> Option<(usize, Vec<Coord>)>
+    where
+        C: Fn(Coord, Coord) -> usize,
+        H: Fn(Coord) -> usize,
+    {
+        let mut came_from = self.blank(None);
+        let mut best = self.blank(usize::MAX);
+        let mut open = BinaryHeap::new();
+        *came_from.index_mut(start.0, start.1) = Some(start);
+        *best.index_mut(start.0, start.1) = 0;
+        open.push(Reverse((heuristic(start), 0, start)));
+        while let Some(Reverse((_, so_far, current))) = open.pop() {
+            if current == goal {
+                return Some((so_far, Self::reconstruct(&came_from, current)));
+            }
+            if so_far > *best.index(current.0, current.1) {
+                // A cheaper route here was already expanded
+                continue;
+            }
+            for next in self.neighbours(current) {
+                let next_cost = so_far + cost(current, next);
+                if next_cost < *best.index(next.0, next.1) {
+                    *best.index_mut(next.0, next.1) = next_cost;
+                    *came_from.index_mut(next.0, next.1) = Some(current);
+                    open.push(Reverse((next_cost + heuristic(next), next_cost, next)));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+fn test_grid() -> Vec2d<u8> {
+    // A wall of 9s with a gap at the bottom
+    Vec2d::new(
+        Vec::from_iter("00900009000090000000".bytes().map(|b| b - b'0')),
+        4,
+        5,
+    )
+}
+
+#[test]
+fn test_bfs() {
+    let grid = test_grid();
+    let search = GridSearch::new(&grid, |_, (i, j)| *grid.index(i, j) != 9);
+    assert_eq!(
+        search.bfs([(0, 0)], |c| c == (0, 4)),
+        Some(vec![
+            (0, 0),
+            (1, 0),
+            (2, 0),
+            (3, 0),
+            (3, 1),
+            (3, 2),
+            (3, 3),
+            (2, 3),
+            (1, 3),
+            (0, 3),
+            (0, 4)
+        ])
+    );
+    assert_eq!(
+        search.bfs([(0, 0), (3, 4)], |c| c == (2, 3)),
+        Some(vec![(3, 4), (2, 4), (2, 3)])
+    );
+    assert_eq!(search.bfs([(0, 0)], |c| c == (0, 2)), None);
+}
+
+#[test]
+fn test_astar() {
+    let grid = test_grid();
+    let search = GridSearch::new(&grid, |_, (i, j)| *grid.index(i, j) != 9);
+    let path = search.astar((0, 0), (0, 4)).unwrap();
+    assert_eq!(path.len(), 11);
+    assert_eq!(path.first(), Some(&(0, 0)));
+    assert_eq!(path.last(), Some(&(0, 4)));
+    assert_eq!(search.astar((0, 0), (0, 2)), None);
+}
+
+#[test]
+fn test_astar_with_diagonals() {
+    let grid = test_grid();
+    let search = GridSearch::new(&grid, |_, (i, j)| *grid.index(i, j) != 9).with_diagonals();
+    assert_eq!(
+        search.astar((0, 0), (0, 4)),
+        Some(vec![(0, 0), (1, 1), (2, 1), (3, 2), (2, 3), (1, 3), (0, 4)])
+    );
+}
+
+#[test]
+fn test_cheapest_path() {
+    let grid = test_grid();
+    let search = GridSearch::new(&grid, |_, _| true);
+    // Stepping onto the wall costs 4, so crossing it beats the 8 steps around it
+    let cost = |_, (i, j): Coord| *grid.index(i, j) as usize / 3 + 1;
+    assert_eq!(search.cheapest_path((0, 0), (0, 4), cost).unwrap().0, 7);
+    assert_eq!(
+        search.cheapest_path((0, 1), (0, 3), cost),
+        Some((5, vec![(0, 1), (0, 2), (0, 3)]))
+    );
+}
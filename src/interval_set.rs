@@ -0,0 +1,232 @@
+use std::ops::RangeInclusive;
+
+/// A set of `u32`s stored as sorted, disjoint ranges. Ranges that touch are merged, so `1..=2`
+/// and `3..=4` are kept as `1..=4`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IntervalSet {
+    ranges: Vec<RangeInclusive<u32>>,
+}
+
+impl IntervalSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds every number in `range`, merging it with any ranges it overlaps or touches.
+    pub fn insert(&mut self, range: RangeInclusive<u32>) {
+        if range.is_empty() {
+            return;
+        }
+        let (mut start, mut end) = range.into_inner();
+        // Widened so that `end + 1` can't overflow at `u32::MAX`
+        let lo = self
+            .ranges
+            .partition_point(|r| (*r.end() as u64) + 1 < start as u64);
+        let hi = self
+            .ranges
+            .partition_point(|r| (*r.start() as u64) <= end as u64 + 1);
+        if lo < hi {
+            start = start.min(*self.ranges[lo].start());
+            end = end.max(*self.ranges[hi - 1].end());
+        }
+        self.ranges.splice(lo..hi, [start..=end]);
+    }
+
+    pub fn ranges(&self) -> &[RangeInclusive<u32>] {
+        &self.ranges
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Whether every number in `range` is in the set.
+    pub fn contains_range(&self, range: &RangeInclusive<u32>) -> bool {
+        let i = self.ranges.partition_point(|r| r.end() < range.start());
+        range.is_empty()
+            || self
+                .ranges
+                .get(i)
+                .is_some_and(|r| r.start() <= range.start() && range.end() <= r.end())
+    }
+
+    /// How many numbers are in the set.
+    pub fn coverage(&self) -> u64 {
+        self.ranges
+            .iter()
+            .map(|r| (r.end() - r.start()) as u64 + 1)
+            .sum()
+    }
+
+    pub fn union(&self, other: &IntervalSet) -> IntervalSet {
+        self.ranges.iter().chain(&other.ranges).cloned().collect()
+    }
+
+    pub fn intersect(&self, other: &IntervalSet) -> IntervalSet {
+        let mut ranges = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while let (Some(a), Some(b)) = (self.ranges.get(i), other.ranges.get(j)) {
+            let (start, end) = (*a.start().max(b.start()), *a.end().min(b.end()));
+            if start <= end {
+                ranges.push(start..=end);
+            }
+            // Whichever finishes first can't overlap anything further along the other
+            if a.end() < b.end() {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        IntervalSet { ranges }
+    }
+
+    /// Everything in this set that isn't in `other`.
+    pub fn subtract(&self, other: &IntervalSet) -> IntervalSet {
+        let mut ranges = Vec::new();
+        let mut j = 0;
+        for range in &self.ranges {
+            let (mut start, end) = (*range.start() as u64, *range.end() as u64);
+            // Skip past the parts of `other` that finish before this range starts
+            while other
+                .ranges
+                .get(j)
+                .is_some_and(|r| (*r.end() as u64) < start)
+            {
+                j += 1;
+            }
+            let mut k = j;
+            while let Some(cut) = other.ranges.get(k).filter(|r| (*r.start() as u64) <= end) {
+                if (*cut.start() as u64) > start {
+                    ranges.push(start as u32..=*cut.start() - 1);
+                }
+                start = *cut.end() as u64 + 1;
+                k += 1;
+            }
+            if start <= end {
+                ranges.push(start as u32..=end as u32);
+            }
+        }
+        IntervalSet { ranges }
+    }
+
+    /// The numbers that fall within at least `k` of `ranges`, found by sweeping across the
+    /// ranges' endpoints in order while keeping count of how many are open.
+    pub fn covered_by_at_least<I>(ranges: I, k: usize) -> IntervalSet
+    where
+        I: IntoIterator<Item = RangeInclusive<u32>>,
+    {
+        assert!(k > 0, "every number is covered by at least 0 ranges");
+        let mut events: Vec<(u64, isize)> = ranges
+            .into_iter()
+            .filter(|r| !r.is_empty())
+            .flat_map(|r| [(*r.start() as u64, 1), (*r.end() as u64 + 1, -1)])
+            .collect();
+        // Closings sort first, so ranges that only touch don't count as overlapping
+        events.sort_unstable();
+        let mut set = IntervalSet::new();
+        let mut open = 0;
+        let mut covered_since = None;
+        for (at, change) in events {
+            open += change;
+            match covered_since {
+                None if open >= k as isize => covered_since = Some(at),
+                Some(since) if open < k as isize => {
+                    set.ranges.push(since as u32..=(at - 1) as u32);
+                    covered_since = None;
+                }
+                _ => {}
+            }
+        }
+        // Ranges that close exactly where the next covered stretch opens are still merged
+        set.ranges.iter().cloned().collect()
+    }
+}
+
+impl FromIterator<RangeInclusive<u32>> for IntervalSet {
+    fn from_iter<I: IntoIterator<Item = RangeInclusive<u32>>>(iter: I) -> Self {
+        let mut ranges: Vec<RangeInclusive<u32>> =
+            iter.into_iter().filter(|r| !r.is_empty()).collect();
+        ranges.sort_unstable_by_key(|r| *r.start());
+        let mut merged: Vec<RangeInclusive<u32>> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if *range.start() as u64 <= *last.end() as u64 + 1 => {
+                    *last = *last.start()..=*last.end().max(range.end());
+                }
+                _ => merged.push(range),
+            }
+        }
+        IntervalSet { ranges: merged }
+    }
+}
+
+impl From<RangeInclusive<u32>> for IntervalSet {
+    fn from(range: RangeInclusive<u32>) -> Self {
+        [range].into_iter().collect()
+    }
+}
+
+#[test]
+fn test_insert() {
+    let mut set = IntervalSet::new();
+    for range in [10..=12, 1..=2, 5..=6, 3..=4, 20..=25, 8..=21] {
+        set.insert(range);
+    }
+    assert_eq!(set.ranges(), [1..=6, 8..=25]);
+    set.insert(u32::MAX - 1..=u32::MAX);
+    set.insert(7..=7);
+    assert_eq!(set.ranges(), [1..=25, u32::MAX - 1..=u32::MAX]);
+    assert_eq!(set.coverage(), 27);
+    assert!(set.contains_range(&(3..=25)) && set.contains_range(&(u32::MAX..=u32::MAX)));
+    assert!(!set.contains_range(&(20..=26)) && !set.contains_range(&(0..=1)));
+    #[allow(clippy::reversed_empty_ranges)]
+    set.insert(5..=4);
+    assert_eq!(set.ranges().len(), 2);
+}
+
+#[test]
+fn test_from_iter() {
+    let set: IntervalSet = [10..=12, 1..=2, 5..=6, 3..=4, 20..=25, 8..=21]
+        .into_iter()
+        .collect();
+    assert_eq!(set.ranges(), [1..=6, 8..=25]);
+}
+
+#[test]
+fn test_set_operations() {
+    let a: IntervalSet = [1..=10, 20..=30].into_iter().collect();
+    let b: IntervalSet = [5..=22, 25..=25, 29..=40].into_iter().collect();
+    assert_eq!(a.union(&b).ranges(), [1..=40]);
+    assert_eq!(
+        a.intersect(&b).ranges(),
+        [5..=10, 20..=22, 25..=25, 29..=30]
+    );
+    assert_eq!(a.subtract(&b).ranges(), [1..=4, 23..=24, 26..=28]);
+    assert_eq!(b.subtract(&a).ranges(), [11..=19, 31..=40]);
+    assert!(a.subtract(&a).is_empty());
+    assert!(IntervalSet::from(3..=4).subtract(&a).is_empty());
+    assert_eq!(
+        IntervalSet::from(0..=u32::MAX)
+            .subtract(&IntervalSet::from(0..=0))
+            .ranges(),
+        [1..=u32::MAX]
+    );
+}
+
+#[test]
+fn test_covered_by_at_least() {
+    let ranges = [1..=5, 3..=8, 4..=4, 7..=10, 11..=12];
+    assert_eq!(
+        IntervalSet::covered_by_at_least(ranges.clone(), 1).ranges(),
+        [1..=12]
+    );
+    assert_eq!(
+        IntervalSet::covered_by_at_least(ranges.clone(), 2).ranges(),
+        [3..=5, 7..=8]
+    );
+    assert_eq!(
+        IntervalSet::covered_by_at_least(ranges.clone(), 3).ranges(),
+        [4..=4]
+    );
+    assert!(IntervalSet::covered_by_at_least(ranges, 4).is_empty());
+}
@@ -1,5 +1,6 @@
 use std::env;
 
+mod bench;
 mod day01;
 mod day02;
 mod day03;
@@ -15,6 +16,8 @@ mod day12;
 mod day13;
 mod day14;
 mod fs;
+mod grid_search;
+mod interval_set;
 mod vec2d;
 
 fn main() {
@@ -22,18 +25,49 @@ fn main() {
     match (&args[1][..], &args[2][..]) {
         ("1", "1") => println!("{}", day01::part_1("./input01.txt")),
         ("1", "2") => println!("{}", day01::part_2("./input01.txt")),
+        ("1", "top") => print!("{}", day01::top("./input01.txt", args[3].parse().unwrap())),
+        ("1", "largest") => print!("{}", day01::largest("./input01.txt")),
+        ("1", "balance") => print!("{}", day01::balance("./input01.txt")),
 
         ("2", "1") => println!("{}", day02::part_1("./input02.txt")),
         ("2", "2") => println!("{}", day02::part_2("./input02.txt")),
+        ("2", "score") => print!("{}", day02::score("./input02.txt", &args[3], &args[4])),
+        ("2", "guide") => print!("{}", day02::guide("./input02.txt")),
 
         ("3", "1") => println!("{}", day03::part_1("./input03.txt")),
         ("3", "2") => println!("{}", day03::part_2("./input03.txt")),
+        ("3", "badges") => println!(
+            "{}",
+            day03::badges("./input03.txt", args[3].parse().unwrap())
+        ),
+        ("3", "bench") => day03::bench("./input03.txt"),
+        ("3", "check") => print!(
+            "{}",
+            day03::check(
+                "./input03.txt",
+                &args[3],
+                args.get(4).map_or(3, |size| size.parse().unwrap())
+            )
+        ),
 
         ("4", "1") => println!("{}", day04::part_1("./input04.txt")),
         ("4", "2") => println!("{}", day04::part_2("./input04.txt")),
+        ("4", "coverage") => print!(
+            "{}",
+            day04::coverage("./input04.txt", args[3].parse().unwrap())
+        ),
+        ("4", "cross") => print!("{}", day04::cross_pairs("./input04.txt")),
 
         ("5", "1") => println!("{}", day05::part_1("./input05.txt")),
         ("5", "2") => println!("{}", day05::part_2("./input05.txt")),
+        ("5", "steps") => print!(
+            "{}",
+            day05::steps(
+                "./input05.txt",
+                &args[3],
+                args.get(4).map_or(1, |every| every.parse().unwrap())
+            )
+        ),
 
         ("6", "1") => println!("{}", day06::part_1("./input06.txt")),
         ("6", "2") => println!("{}", day06::part_2("./input06.txt")),
@@ -52,15 +86,45 @@ fn main() {
 
         ("11", "1") => println!("{}", day11::part_1("./input11.txt")),
         ("11", "2") => println!("{}", day11::part_2("./input11.txt")),
+        ("11", "after") => match day11::monkey_business_after(
+            "./input11.txt",
+            args[3].parse().unwrap(),
+            args[4].parse().unwrap(),
+        ) {
+            Ok(monkey_business) => println!("{}", monkey_business),
+            Err(e) => eprintln!("{}", e),
+        },
+        ("11", "trace") => print!("{}", day11::trace("./input11.txt", &args[3], &args[4])),
 
         ("12", "1") => println!("{}", day12::part_1("./input12.txt")),
         ("12", "2") => println!("{}", day12::part_2("./input12.txt")),
+        ("12", "bench") => day12::bench("./input12.txt"),
+        ("12", "route") => print!(
+            "{}",
+            day12::route(
+                "./input12.txt",
+                args[3].parse().unwrap(),
+                args[4].parse().unwrap(),
+                args[5].parse().unwrap(),
+                args[6].parse().unwrap()
+            )
+        ),
+        ("12", "path") => print!("{}", day12::path("./input12.txt")),
 
         ("13", "1") => println!("{}", day13::part_1("./input13.txt")),
         ("13", "2") => println!("{}", day13::part_2("./input13.txt")),
+        ("13", "ranks") => print!("{}", day13::ranks("./input13.txt", &args[3..])),
+        ("13", "explain") => print!("{}", day13::explain("./input13.txt")),
+        #[cfg(feature = "json-packets")]
+        ("13", "bench") => day13::bench("./input13.txt"),
 
         ("14", "1") => println!("{}", day14::part_1("./input14.txt")),
-        ("14", "2") => println!("{}", day14::part_1("./input14.txt")),
+        ("14", "2") => println!("{}", day14::part_2("./input14.txt")),
+        ("14", "bench") => day14::bench("./input14.txt"),
+        ("14", "pour") => print!(
+            "{}",
+            day14::pour_from("./input14.txt", args[3].parse().ok(), &args[4..])
+        ),
 
         _ => panic!("Unimplemented puzzle"),
     }